@@ -0,0 +1,192 @@
+//! PFC(Predictive Functional Controller)によるバネ・マス・ダンパ系の位置制御
+//!
+//! cargo run --example mass_spring_damper で実行し，結果を result.csv などに書き出す．
+
+use std::fs;
+use std::io::{Write, BufWriter};
+use nalgebra::{DVector, DMatrix};
+use pfc_dynamic::{analysis, basis, c2d, designer, metrics, robustness, trajectory, tuner, Controller, Error, Setpoint, StateSpace};
+
+/// バネ・マス・ダンパ系の連続時間状態空間モデル（状態は位置と速度，出力は位置）
+///
+/// * m: 質量[kg]
+/// * c: 減衰係数[Ns/m]
+/// * k: バネ定数[N/m]
+fn mass_spring_damper(m: f64, c: f64, k: f64) -> Result<StateSpace<f64>, Error> {
+    StateSpace::new(
+        DMatrix::from_iterator(2, 2, [
+            0.0, 1.0,
+            -k/m, -c/m
+        ].iter().cloned()).transpose(),  // 正方行列は何故か転置して作られるっぽい
+        DMatrix::from_iterator(2, 1, [
+            0.0,
+            1.0/m
+        ].iter().cloned()),
+        DMatrix::from_iterator(1, 2, [
+            1.0, 0.0
+        ].iter().cloned()),
+        DMatrix::from_iterator(1, 1, [
+            0.0
+        ].iter().cloned()),
+        0.0
+    )
+}
+
+/// この例で使う制約とオフセットフリーモードの設定でPFCを設計する．
+///
+/// * plant: 離散時間状態空間モデル
+/// * candidate: 閉ループ応答時間・基底関数の個数・一致点の個数
+fn design_pfc(plant: &StateSpace<f64>, candidate: &tuner::Candidate) -> Result<designer::PFC, Error> {
+    let input = designer::InputChannel {
+        n_b: candidate.n_b,
+        basis: basis::Basis::Polynomial,  // 基底関数
+        limit: [-5.0, 5.0],  // 制御入力[N]
+    };
+    let output = designer::OutputChannel {
+        coincidence: designer::Coincidence::Uniform(candidate.n_h),  // 一致点
+        t_clrt: candidate.t_clrt,
+        trajectory: trajectory::ReferenceTrajectory::FirstOrder,  // 参照軌道
+    };
+    let mut pfc = designer::PFC::new(plant, &[input], &[output])?;
+    pfc.set_rate_limit(Some(&[[-2.0, 2.0]]))?;  // 1サンプルあたりの入力変化量[N]
+    pfc.set_state_limit(Some(&[(1, [-0.15, 0.15])]))?;  // 速度[m/s]
+    pfc.enable_offset_free(1e-6, 1e-2, 1e-6)?;
+    Ok(pfc)
+}
+
+fn main() {
+    // CSVファイルにデータ保存（同一ファイルが存在したら上書き）
+    let mut file = BufWriter::new(fs::File::create("result.csv").unwrap());
+
+    // バネ・マス・ダンパ系
+    let m = 5.0;  // [kg]
+    let c = 5.0;  // [Ns/m]
+    let k = 5.0;  // [N/m]
+    let plant_c = mass_spring_damper(m, c, k).unwrap();
+
+    // 離散化してPFCを設計
+    let plant = c2d(plant_c, 0.05).unwrap().with_delay(3);
+    let candidate = tuner::Candidate {t_clrt: 0.5, n_b: 2, n_h: 3};
+    let mut pfc = design_pfc(&plant, &candidate).unwrap();
+
+    // 閉ループ応答時間・基底関数の個数・一致点の個数の候補から評価関数が最小のものを探す
+    let tuning = tuner::tune_pfc(
+        &plant, |candidate| design_pfc(&plant, candidate),
+        &tuner::SearchSpace {t_clrt: vec![0.3, 0.5, 0.8, 1.2], n_b: vec![1, 2, 3], n_h: vec![2, 3, 4, 6]},
+        &tuner::Objective {settling_time: 1.0, overshoot: 0.05, input_energy: 0.0, peak_sensitivity: 0.5},
+        &DVector::from_element(1, 0.1), 160, 0.02
+    ).unwrap();
+    let best = tuning.best;
+    println!(
+        "自動調整の結果: t_clrt = {}, n_b = {}, n_h = {}（整定時間 {:.2} [s]，オーバーシュート {:.2} [%]，入力エネルギー {:.4}，感度関数のピーク値 {:.4}，{}候補中）",
+        best.candidate.t_clrt, best.candidate.n_b, best.candidate.n_h,
+        best.settling_time, best.overshoot, best.input_energy, best.peak_sensitivity.unwrap(), tuning.evaluations.len()
+    );
+
+    // 質量・減衰係数・バネ定数が公称値から±30%ずれたときのロバスト性を評価
+    let robustness = robustness::sweep_pfc(
+        &pfc,
+        |p| c2d(mass_spring_damper(p[0], p[1], p[2])?, plant.sample_time()).map(|sys| sys.with_delay(plant.delay())),
+        &[[0.7 * m, 1.3 * m], [0.7 * c, 1.3 * c], [0.7 * k, 1.3 * k]],
+        robustness::Sampling::Grid(3),
+        &DVector::from_element(1, 0.1), 160, 0.02
+    ).unwrap();
+    println!("不安定になるパラメータの組: {} / {}", robustness.n_unstable(), robustness.points.len());
+    println!("閉ループ極の最大絶対値の最悪値: {:.6}", robustness.points.iter().filter_map(|p| p.spectral_radius).fold(0.0, f64::max));
+    if let Some(region) = robustness.unstable_region() {
+        println!("不安定になる範囲 (m, c, k): {:?}", region);
+    }
+    if let Some(worst) = robustness.worst_overshoot() {
+        println!("最大オーバーシュート: {:.2} [%] (m, c, k) = {:?}", worst.overshoot(), worst.parameters);
+    }
+    if let Some(worst) = robustness.worst_settling_time() {
+        println!("最長整定時間: {:.2} [s] (m, c, k) = {:?}", worst.settling_time(), worst.parameters);
+    }
+
+    // 設計結果を表示してJSONで保存
+    print!("{}", pfc.report());
+    fs::write("design_report.json", pfc.report().to_json()).unwrap();
+
+    // 制約を外したときの閉ループ系の安定性を確認
+    let closed_loop = analysis::closed_loop(&plant, &pfc).unwrap();
+    println!("閉ループ系の安定性: {}", closed_loop.is_stable());
+    println!("閉ループ極の最大絶対値: {:.6}", closed_loop.poles.iter().map(|p| p.norm_sqr().sqrt()).fold(0.0, f64::max));
+    print!("目標値から出力までの直流ゲイン ={}", closed_loop.dc_gain);
+    print!("感度関数の直流ゲイン ={}", analysis::dc_gain(&closed_loop.sensitivity));
+    print!("相補感度関数の直流ゲイン ={}", analysis::dc_gain(&closed_loop.complementary));
+
+    // 制御対象の入力で切った開ループの安定余裕と周波数応答
+    let margins = analysis::margins(&plant, &pfc).unwrap()[0];
+    println!("ゲイン余裕: {:.4} ({:.4} [rad/s])", margins.gain_margin, margins.phase_crossover);
+    println!("位相余裕: {:.4} [deg] ({:.4} [rad/s])", margins.phase_margin, margins.gain_crossover);
+    println!("むだ時間余裕: {:.4} [s]", margins.delay_margin);
+    println!("感度関数のピーク値: {:.4}", margins.peak_sensitivity);
+    let loop_tf = analysis::open_loop(&plant, &pfc).unwrap();
+    let omega = analysis::log_space(0.01, std::f64::consts::PI / plant.sample_time(), 200);
+    let bode = analysis::bode(&loop_tf, &omega, 0, 0).unwrap();
+    let nyquist = analysis::nyquist(&loop_tf, &omega, 0, 0).unwrap();
+    let mut freq_file = BufWriter::new(fs::File::create("frequency_response.csv").unwrap());
+    for ((w, (gain, phase)), l) in omega.iter().zip(bode.iter()).zip(nyquist.iter()) {
+        freq_file.write_all(format!("{:.6},{:.6},{:.6},{:.6},{:.6}\n", w, gain, phase, l.re, l.im).as_bytes()).unwrap();
+    }
+
+    let mut n_active = 0;  // 入力制約が掛かったサンプル数
+    let mut n_infeasible = 0;  // 状態制約を満たせなかったサンプル数
+
+    // むだ時間を含めてシミュレーションする
+    let plant_sim = plant.delay_augmented();
+    let mut x = DVector::zeros(plant_sim.a().nrows());
+    let mut y_log = Vec::new();
+    let mut u_log = Vec::new();
+
+    // 目標値（2〜3[s]で0.1[m]まで移動する台形プロファイル）は事前に分かっているものとして先読みさせる
+    let r_profile: Vec<DVector<f64>> = (0..=160)
+        .map(|i| DVector::from_element(1, 0.1 * ((i as f64 - 40.0) / 20.0).clamp(0.0, 1.0)))
+        .collect();
+    for i in 0..=160 {
+        let r = &r_profile[i];
+        let d = DVector::from_element(1, if i <= 100 {0.0} else {-1.0});  // 入力外乱[N]
+        let y = plant_sim.c() * &x;
+
+        // 制御入力を計算（制御則に共通のトレイト経由で動かす）
+        let u = Controller::update(&mut pfc, &Setpoint::Sequence(&r_profile[i..]), &y);
+        if pfc.active_constraint()[0] != designer::ActiveConstraint::None {
+            n_active += 1;
+        }
+        if !pfc.is_feasible() {
+            n_infeasible += 1;
+        }
+
+        // 制御対象の状態を更新
+        x = plant_sim.a() * &x + plant_sim.b() * (&u + &d);
+        y_log.push(y[0]);
+        u_log.push(u[0]);
+
+        // データ保存
+        file.write_all(format!(
+            "{:.4},{:.4},{:.4},{:.4},{:.4},{:.4}\n",
+            plant.sample_time() * i as f64, r[0], y[0], u[0], pfc.limit()[0][0], pfc.limit()[0][1]
+        ).as_bytes()).unwrap();
+    }
+
+    // 外乱が入る前までの応答の性能指標
+    let r_log: Vec<f64> = r_profile.iter().map(|r| r[0]).collect();
+    let step = metrics::step_metrics(plant.sample_time(), &r_log[..=100], &y_log[..=100], 0.02).unwrap();
+    let input = metrics::input_metrics(&u_log, pfc.limit()[0]);
+    println!("立ち上がり時間: {:.2} [s]，整定時間: {:.2} [s]，オーバーシュート: {:.2} [%]，定常偏差: {:.2e} [m]",
+        step.rise_time, step.settling_time, step.overshoot, step.steady_state_error);
+    println!("IAE: {:.4e}，ISE: {:.4e}，ITAE: {:.4e}", step.iae, step.ise, step.itae);
+    println!("入力の全変動: {:.4} [N]，入力制約に張り付いていた割合: {:.2} [%]", input.total_variation, 100.0 * input.saturation_duty);
+    println!("入力制約が掛かったサンプル数: {}", n_active);
+    println!("状態制約を満たせなかったサンプル数: {}", n_infeasible);
+    println!("内部モデルの分解: {}", pfc.is_decomposed());
+    println!("入力外乱の推定値: {:.4} [N]", pfc.disturbance()[0]);
+
+    // 状態制約を外した設計のゲインをファームウェア（pfc-runtime）向けに書き出し，Q16での量子化誤差を表示する
+    let mut pfc_runtime = design_pfc(&plant, &candidate).unwrap();
+    pfc_runtime.set_state_limit(None).unwrap();
+    let gains = pfc_runtime.serialize_gains().unwrap();
+    fs::write("pfc_gains.bin", &gains).unwrap();
+    println!("書き出したゲイン: {} [byte]", gains.len());
+    print!("{}", pfc_runtime.quantize::<16>().unwrap());
+}
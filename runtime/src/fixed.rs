@@ -0,0 +1,228 @@
+//! FPUのないマイコン向けの固定小数点数
+//!
+//! Q形式（符号付き32bit，小数部 FRAC bit）で，演算は全て飽和させる．
+//! RuntimeScalar を実装しているので StaticPFC のスカラー型にそのまま使える．
+
+use core::fmt;
+use core::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
+use num_traits::{FromPrimitive, One, Zero};
+
+/// 小数部が FRAC bit の固定小数点数（値は raw / 2^FRAC）
+///
+/// 1.0 を表せるように FRAC は31未満でなければならず，それ以外はコンパイルエラーになる．
+///
+/// ```compile_fail
+/// let _ = pfc_runtime::fixed::Fixed::<31>::from_f64(0.5);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Fixed<const FRAC: u32>(i32);
+
+/// 小数部16bitの固定小数点数（範囲 ±32768，分解能 約1.5e-5）
+pub type Q16 = Fixed<16>;
+
+impl<const FRAC: u32> Fixed<FRAC> {
+    /// 表せる最大値
+    pub const MAX: Self = Self(i32::MAX);
+    /// 表せる最小値
+    pub const MIN: Self = Self(i32::MIN);
+    /// 分解能（最下位bitの重み）
+    pub const EPSILON: Self = Self(1);
+    /// FRAC の範囲の確認（値を扱う関数で参照してコンパイル時に評価させる）
+    const FRAC_IS_VALID: () = assert!(FRAC < 31, "Fixed<FRAC> needs FRAC < 31 to represent 1.0");
+
+    /// 内部表現から作る．
+    pub const fn from_raw(raw: i32) -> Self {
+        Self(raw)
+    }
+
+    /// 内部表現を返す．
+    pub const fn raw(self) -> i32 {
+        self.0
+    }
+
+    /// 最も近い値に丸めて変換する（範囲外や非有限値ならNone）．
+    pub fn from_f64(v: f64) -> Option<Self> {
+        let () = Self::FRAC_IS_VALID;
+        // core には f64::round がないので，0から遠ざかる向きに0.5を足して切り捨てる
+        let scaled = v * (1u64 << FRAC) as f64;
+        let rounded = if scaled >= 0.0 {scaled + 0.5} else {scaled - 0.5};
+        if rounded.is_nan() || rounded <= i32::MIN as f64 - 1.0 || rounded >= i32::MAX as f64 + 1.0 {
+            return None;
+        }
+        Some(Self(rounded as i32))
+    }
+
+    /// f64に変換する．
+    pub fn to_f64(self) -> f64 {
+        let () = Self::FRAC_IS_VALID;
+        self.0 as f64 / (1u64 << FRAC) as f64
+    }
+
+    fn saturate(v: i64) -> Self {
+        Self(v.clamp(i32::MIN as i64, i32::MAX as i64) as i32)
+    }
+}
+
+impl<const FRAC: u32> fmt::Display for Fixed<FRAC> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.to_f64(), f)
+    }
+}
+
+impl<const FRAC: u32> Add for Fixed<FRAC> {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self(self.0.saturating_add(rhs.0))
+    }
+}
+
+impl<const FRAC: u32> Sub for Fixed<FRAC> {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        Self(self.0.saturating_sub(rhs.0))
+    }
+}
+
+impl<const FRAC: u32> Mul for Fixed<FRAC> {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        let () = Self::FRAC_IS_VALID;
+        // 積は小数部が 2 FRAC bit になるので，丸めてから FRAC bit 戻す
+        let half = if FRAC == 0 {0} else {1i64 << (FRAC - 1)};
+        Self::saturate((self.0 as i64 * rhs.0 as i64 + half) >> FRAC)
+    }
+}
+
+impl<const FRAC: u32> Div for Fixed<FRAC> {
+    type Output = Self;
+    fn div(self, rhs: Self) -> Self {
+        let () = Self::FRAC_IS_VALID;
+        // 0除算は符号に応じて飽和させる（制御周期中に panic させない）
+        if rhs.0 == 0 {
+            return if self.0 >= 0 {Self::MAX} else {Self::MIN};
+        }
+        Self::saturate(((self.0 as i64) << FRAC) / rhs.0 as i64)
+    }
+}
+
+impl<const FRAC: u32> Neg for Fixed<FRAC> {
+    type Output = Self;
+    fn neg(self) -> Self {
+        Self(self.0.saturating_neg())
+    }
+}
+
+impl<const FRAC: u32> AddAssign for Fixed<FRAC> {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl<const FRAC: u32> SubAssign for Fixed<FRAC> {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = *self - rhs;
+    }
+}
+
+impl<const FRAC: u32> MulAssign for Fixed<FRAC> {
+    fn mul_assign(&mut self, rhs: Self) {
+        *self = *self * rhs;
+    }
+}
+
+impl<const FRAC: u32> DivAssign for Fixed<FRAC> {
+    fn div_assign(&mut self, rhs: Self) {
+        *self = *self / rhs;
+    }
+}
+
+impl<const FRAC: u32> Zero for Fixed<FRAC> {
+    fn zero() -> Self {
+        Self(0)
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl<const FRAC: u32> One for Fixed<FRAC> {
+    fn one() -> Self {
+        let () = Self::FRAC_IS_VALID;
+        Self(1 << FRAC)
+    }
+}
+
+impl<const FRAC: u32> FromPrimitive for Fixed<FRAC> {
+    fn from_i64(n: i64) -> Option<Self> {
+        let () = Self::FRAC_IS_VALID;
+        let raw = n.checked_mul(1 << FRAC)?;
+        i32::try_from(raw).ok().map(Self)
+    }
+
+    fn from_u64(n: u64) -> Option<Self> {
+        Self::from_i64(i64::try_from(n).ok()?)
+    }
+
+    fn from_f64(v: f64) -> Option<Self> {
+        Fixed::from_f64(v)
+    }
+
+    fn from_f32(v: f32) -> Option<Self> {
+        Fixed::from_f64(v as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_f64_rounds_to_nearest() {
+        let eps = Q16::EPSILON.to_f64();
+        assert_eq!(Q16::from_f64(0.5 * eps).unwrap().raw(), 1);
+        assert_eq!(Q16::from_f64(-0.5 * eps).unwrap().raw(), -1);
+        assert_eq!(Q16::from_f64(0.49 * eps).unwrap().raw(), 0);
+        assert_eq!(Q16::from_f64(1.25).unwrap().to_f64(), 1.25);
+        assert_eq!(Q16::one().to_f64(), 1.0);
+        assert_eq!(Fixed::<30>::one().to_f64(), 1.0);
+    }
+
+    #[test]
+    fn from_f64_rejects_unrepresentable_values() {
+        assert!(Q16::from_f64(32768.0).is_none());
+        assert!(Q16::from_f64(-32768.0).is_some());
+        assert!(Q16::from_f64(f64::NAN).is_none());
+        assert!(Q16::from_f64(f64::INFINITY).is_none());
+        assert!(<Q16 as FromPrimitive>::from_i64(40000).is_none());
+    }
+
+    #[test]
+    fn arithmetic_saturates() {
+        let big = Q16::from_f64(30000.0).unwrap();
+        assert_eq!(big + big, Q16::MAX);
+        assert_eq!(-big - big, Q16::MIN);
+        assert_eq!(big * big, Q16::MAX);
+        assert_eq!(big * -big, Q16::MIN);
+        assert_eq!(big / Q16::from_f64(0.5).unwrap(), Q16::MAX);
+        assert_eq!(-big / Q16::from_f64(0.5).unwrap(), Q16::MIN);
+        assert_eq!(-Q16::MIN, Q16::MAX);
+    }
+
+    #[test]
+    fn multiplication_rounds_and_division_is_exact_when_representable() {
+        let half_eps = Q16::from_f64(0.5).unwrap() * Q16::EPSILON;
+        assert_eq!(half_eps.raw(), 1);
+        let x = Q16::from_f64(1.5).unwrap() * Q16::from_f64(-2.25).unwrap();
+        assert_eq!(x.to_f64(), -3.375);
+        assert_eq!((Q16::from_f64(3.0).unwrap() / Q16::from_f64(-1.5).unwrap()).to_f64(), -2.0);
+    }
+
+    #[test]
+    fn division_by_zero_saturates() {
+        let zero = Q16::zero();
+        assert_eq!(Q16::one() / zero, Q16::MAX);
+        assert_eq!(-Q16::one() / zero, Q16::MIN);
+        assert_eq!(zero / zero, Q16::MAX);
+    }
+}
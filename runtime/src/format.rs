@@ -0,0 +1,336 @@
+//! ホスト側の設計器からマイコンへゲインを渡すためのバイナリ形式
+//!
+//! 全てリトルエンディアンで，ヘッダ・一致点の並び・実数値の順に並べる．
+//! 実数値は全て f64 で書き，読み込むときに実行時のスカラー型へ変換する．
+//! 形式を変えるときは VERSION を上げること．
+//!
+//! ヘッダ（HEADER_LEN バイト）
+//! * 0..4: MAGIC
+//! * 4..6: VERSION (u16)
+//! * 6..8: 省略可能な要素の有無を表すフラグ (u16, FLAG_*)
+//! * 8..28: 状態数 N, 入力数 L, 出力数 P, 一致点の総数 H, 入力むだ時間 (u32 × 5)
+//!
+//! 一致点の並び（H × 8 バイト）
+//! * 各一致点の (出力チャネル, むだ時間を除いたサンプル時刻) (u32 × 2)
+//!
+//! 実数値（f64．行列は列優先）
+//! * a_m (N×N), b_m (N×L), c_m (P×N), k_0 (L×P), nu_x (L×N), nu_r (L×H)
+//! * FLAG_OBSERVER のとき: オブザーバゲインの状態部分 (N×P), 外乱部分 (L×P)
+//! * FLAG_STABILIZER のとき: 安定化ゲイン (N×P)
+//! * 入力制約 (L × \[下限, 上限\])
+//! * FLAG_RATE_LIMIT のとき: 変化率制約 (L × \[下限, 上限\])
+
+use core::fmt;
+use nalgebra::SMatrix;
+use num_traits::FromPrimitive;
+
+use super::{RuntimeScalar, StaticGains, StaticPFC};
+
+/// 形式の識別子
+pub const MAGIC: [u8; 4] = *b"PFCG";
+/// 形式のバージョン
+pub const VERSION: u16 = 1;
+/// ヘッダのバイト数
+pub const HEADER_LEN: usize = 28;
+
+/// オフセットフリーモードのオブザーバゲインを含む
+pub const FLAG_OBSERVER: u16 = 1 << 0;
+/// 内部モデルの安定化ゲインを含む
+pub const FLAG_STABILIZER: u16 = 1 << 1;
+/// 変化率制約を含む
+pub const FLAG_RATE_LIMIT: u16 = 1 << 2;
+
+/// ゲインの読み込みで発生するエラー
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FormatError {
+    /// 識別子が一致しない
+    BadMagic,
+    /// 対応していないバージョン
+    UnsupportedVersion(u16),
+    /// ヘッダの次元が読み込み先の静的サイズと一致しない
+    DimensionMismatch,
+    /// バイト数がヘッダから決まる長さと一致しない
+    LengthMismatch,
+    /// 実数値を実行時のスカラー型で表せない
+    InvalidValue,
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FormatError::BadMagic => write!(f, "Not a PFC gain file."),
+            FormatError::UnsupportedVersion(v) => write!(f, "Unsupported gain format version: {}", v),
+            FormatError::DimensionMismatch => write!(f, "Dimensions of the gains do not match the static PFC."),
+            FormatError::LengthMismatch => write!(f, "Length of the gain data does not match its header."),
+            FormatError::InvalidValue => write!(f, "Gain value is not representable by the runtime scalar type."),
+        }
+    }
+}
+
+/// ヘッダ
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub flags: u16,  // 省略可能な要素の有無（FLAG_*）
+    pub n: u32,      // 内部モデルの状態数（むだ時間分を含む）
+    pub l: u32,      // 入力数
+    pub p: u32,      // 出力数
+    pub h: u32,      // 一致点の総数
+    pub delay: u32,  // 入力むだ時間[サンプル]
+}
+
+impl Header {
+    /// バイト列に変換する．
+    pub fn encode(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0; HEADER_LEN];
+        bytes[0..4].copy_from_slice(&MAGIC);
+        bytes[4..6].copy_from_slice(&VERSION.to_le_bytes());
+        bytes[6..8].copy_from_slice(&self.flags.to_le_bytes());
+        for (k, v) in [self.n, self.l, self.p, self.h, self.delay].iter().enumerate() {
+            bytes[8 + 4 * k..12 + 4 * k].copy_from_slice(&v.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// バイト列の先頭からヘッダを読む．
+    pub fn decode(bytes: &[u8]) -> Result<Self, FormatError> {
+        if bytes.len() < HEADER_LEN {
+            return Err(FormatError::LengthMismatch);
+        }
+        if bytes[0..4] != MAGIC {
+            return Err(FormatError::BadMagic);
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != VERSION {
+            return Err(FormatError::UnsupportedVersion(version));
+        }
+        let u32_at = |k: usize| u32::from_le_bytes([bytes[8 + 4 * k], bytes[9 + 4 * k], bytes[10 + 4 * k], bytes[11 + 4 * k]]);
+        Ok(Self {
+            flags: u16::from_le_bytes([bytes[6], bytes[7]]),
+            n: u32_at(0),
+            l: u32_at(1),
+            p: u32_at(2),
+            h: u32_at(3),
+            delay: u32_at(4),
+        })
+    }
+
+    /// ヘッダを含む全体のバイト数
+    pub fn total_len(&self) -> usize {
+        let (n, l, p, h) = (self.n as usize, self.l as usize, self.p as usize, self.h as usize);
+        let mut n_values = n * n + n * l + p * n + l * p + l * n + l * h + 2 * l;
+        if self.flags & FLAG_OBSERVER != 0 {
+            n_values += n * p + l * p;
+        }
+        if self.flags & FLAG_STABILIZER != 0 {
+            n_values += n * p;
+        }
+        if self.flags & FLAG_RATE_LIMIT != 0 {
+            n_values += 2 * l;
+        }
+        HEADER_LEN + 8 * h + 8 * n_values
+    }
+}
+
+/// バイト列を先頭から順に読む．長さはヘッダで確認済みとする．
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn u32(&mut self) -> u32 {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(&self.bytes[self.pos..self.pos + 4]);
+        self.pos += 4;
+        u32::from_le_bytes(buf)
+    }
+
+    fn value<T: FromPrimitive>(&mut self) -> Result<T, FormatError> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(&self.bytes[self.pos..self.pos + 8]);
+        self.pos += 8;
+        T::from_f64(f64::from_le_bytes(buf)).ok_or(FormatError::InvalidValue)
+    }
+
+    fn matrix<T: RuntimeScalar + FromPrimitive, const R: usize, const C: usize>(&mut self) -> Result<SMatrix<T, R, C>, FormatError> {
+        let mut m = SMatrix::<T, R, C>::zeros();
+        for v in m.iter_mut() {
+            *v = self.value()?;
+        }
+        Ok(m)
+    }
+
+    fn limits<T: RuntimeScalar + FromPrimitive, const L: usize>(&mut self) -> Result<[[T; 2]; L], FormatError> {
+        let mut limit = [[T::zero(); 2]; L];
+        for range in limit.iter_mut() {
+            range[0] = self.value()?;
+            range[1] = self.value()?;
+        }
+        Ok(limit)
+    }
+}
+
+impl<T: RuntimeScalar + FromPrimitive, const N: usize, const L: usize, const P: usize, const H: usize> StaticPFC<T, N, L, P, H> {
+    /// ホスト側で書き出したゲインを読み込み，初期状態のPFCを作る．
+    ///
+    /// --- Arguments ---
+    /// * bytes: PFC::serialize_gains で書き出したバイト列
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, FormatError> {
+        let header = Header::decode(bytes)?;
+        if (header.n as usize, header.l as usize, header.p as usize, header.h as usize) != (N, L, P, H) {
+            return Err(FormatError::DimensionMismatch);
+        }
+        if bytes.len() != header.total_len() {
+            return Err(FormatError::LengthMismatch);
+        }
+
+        let mut reader = Reader {bytes, pos: HEADER_LEN};
+        let mut horizons = [(0, 0); H];
+        for horizon in horizons.iter_mut() {
+            let i = reader.u32() as usize;
+            if i >= P {
+                return Err(FormatError::DimensionMismatch);
+            }
+            *horizon = (i, reader.u32());
+        }
+        let a_m = reader.matrix()?;
+        let b_m = reader.matrix()?;
+        let c_m = reader.matrix()?;
+        let k_0 = reader.matrix()?;
+        let nu_x = reader.matrix()?;
+        let nu_r = reader.matrix()?;
+        let observer = if header.flags & FLAG_OBSERVER != 0 {
+            Some((reader.matrix()?, reader.matrix()?))
+        } else {
+            None
+        };
+        let stabilizer = if header.flags & FLAG_STABILIZER != 0 {
+            Some(reader.matrix()?)
+        } else {
+            None
+        };
+        let gains = StaticGains {a_m, b_m, c_m, k_0, nu_x, nu_r, horizons, delay: header.delay, observer, stabilizer};
+
+        let mut pfc = Self::new(gains, reader.limits()?);
+        if header.flags & FLAG_RATE_LIMIT != 0 {
+            pfc.rate_limit = Some(reader.limits()?);
+        }
+        Ok(pfc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+    use super::*;
+    use crate::fixed::Q16;
+    use crate::tests::example_gains;
+
+    /// 形式の説明どおりにバイト列を書く（ホスト側の PFC::serialize_gains と同じ並び）
+    fn encode<const N: usize, const L: usize, const P: usize, const H: usize>(pfc: &StaticPFC<f64, N, L, P, H>) -> Vec<u8> {
+        let g = pfc.gains();
+        let mut flags = 0;
+        if g.observer.is_some() {
+            flags |= FLAG_OBSERVER;
+        }
+        if g.stabilizer.is_some() {
+            flags |= FLAG_STABILIZER;
+        }
+        if pfc.rate_limit.is_some() {
+            flags |= FLAG_RATE_LIMIT;
+        }
+        let header = Header {flags, n: N as u32, l: L as u32, p: P as u32, h: H as u32, delay: g.delay};
+        let mut bytes = Vec::from(header.encode());
+        for &(i, h) in g.horizons.iter() {
+            bytes.extend_from_slice(&(i as u32).to_le_bytes());
+            bytes.extend_from_slice(&h.to_le_bytes());
+        }
+        let mut values: Vec<f64> = Vec::new();
+        values.extend(g.a_m.iter().chain(g.b_m.iter()).chain(g.c_m.iter()));
+        values.extend(g.k_0.iter().chain(g.nu_x.iter()).chain(g.nu_r.iter()));
+        if let Some((l_x, l_d)) = &g.observer {
+            values.extend(l_x.iter().chain(l_d.iter()));
+        }
+        if let Some(stabilizer) = &g.stabilizer {
+            values.extend(stabilizer.iter());
+        }
+        values.extend(pfc.limit.iter().flatten());
+        if let Some(rate_limit) = &pfc.rate_limit {
+            values.extend(rate_limit.iter().flatten());
+        }
+        for v in values {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        assert_eq!(bytes.len(), header.total_len());
+        bytes
+    }
+
+    fn example_bytes() -> Vec<u8> {
+        let mut pfc = StaticPFC::new(example_gains(), [[-5.0, 5.0]]);
+        pfc.rate_limit = Some([[-2.0, 2.0]]);
+        encode(&pfc)
+    }
+
+    #[test]
+    fn round_trip_keeps_gains_and_limits() {
+        let pfc = StaticPFC::<f64, 2, 1, 1, 2>::from_bytes(&example_bytes()).unwrap();
+        assert_eq!(pfc.gains(), &example_gains());
+        assert_eq!(pfc.limit, [[-5.0, 5.0]]);
+        assert_eq!(pfc.rate_limit, Some([[-2.0, 2.0]]));
+
+        // 省略可能な要素がない場合
+        let mut gains = example_gains();
+        gains.observer = None;
+        gains.stabilizer = Some(SMatrix::from_row_slice(&[0.7, 0.8]));
+        let bytes = encode(&StaticPFC::new(gains, [[-1.0, 1.0]]));
+        let pfc = StaticPFC::<f64, 2, 1, 1, 2>::from_bytes(&bytes).unwrap();
+        assert_eq!(pfc.gains(), &gains);
+        assert_eq!(pfc.rate_limit, None);
+    }
+
+    #[test]
+    fn bad_magic_and_version_are_rejected() {
+        let mut bytes = example_bytes();
+        bytes[0] = b'X';
+        assert_eq!(StaticPFC::<f64, 2, 1, 1, 2>::from_bytes(&bytes).unwrap_err(), FormatError::BadMagic);
+
+        let mut bytes = example_bytes();
+        bytes[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert_eq!(StaticPFC::<f64, 2, 1, 1, 2>::from_bytes(&bytes).unwrap_err(), FormatError::UnsupportedVersion(VERSION + 1));
+    }
+
+    #[test]
+    fn wrong_length_is_rejected() {
+        let bytes = example_bytes();
+        for len in [0, HEADER_LEN - 1, bytes.len() - 1] {
+            assert_eq!(StaticPFC::<f64, 2, 1, 1, 2>::from_bytes(&bytes[..len]).unwrap_err(), FormatError::LengthMismatch);
+        }
+        let mut longer = bytes.clone();
+        longer.push(0);
+        assert_eq!(StaticPFC::<f64, 2, 1, 1, 2>::from_bytes(&longer).unwrap_err(), FormatError::LengthMismatch);
+    }
+
+    #[test]
+    fn wrong_dimensions_are_rejected() {
+        let bytes = example_bytes();
+        assert_eq!(StaticPFC::<f64, 3, 1, 1, 2>::from_bytes(&bytes).unwrap_err(), FormatError::DimensionMismatch);
+        assert_eq!(StaticPFC::<f64, 2, 1, 1, 3>::from_bytes(&bytes).unwrap_err(), FormatError::DimensionMismatch);
+
+        // 一致点の出力チャネルが出力数を超える
+        let mut bytes = bytes;
+        bytes[HEADER_LEN..HEADER_LEN + 4].copy_from_slice(&1u32.to_le_bytes());
+        assert_eq!(StaticPFC::<f64, 2, 1, 1, 2>::from_bytes(&bytes).unwrap_err(), FormatError::DimensionMismatch);
+    }
+
+    #[test]
+    fn unrepresentable_value_is_rejected() {
+        // Q16 は ±32768 までしか表せない
+        let mut pfc = StaticPFC::new(example_gains(), [[-1e6, 1e6]]);
+        pfc.rate_limit = None;
+        let bytes = encode(&pfc);
+        assert_eq!(StaticPFC::<Q16, 2, 1, 1, 2>::from_bytes(&bytes).unwrap_err(), FormatError::InvalidValue);
+        assert!(StaticPFC::<f64, 2, 1, 1, 2>::from_bytes(&bytes).is_ok());
+    }
+}
@@ -0,0 +1,263 @@
+//! 組み込み向けの静的サイズのPFC
+//!
+//! 行列サイズを const generics で与え，SMatrix だけで実行するのでヒープを使わない．
+//! core の機能だけを使う no_std のクレートで，マイコンのファームウェアはこのクレートだけをリンクすればよい．
+//! 設計はホスト側の pfc-dynamic クレートで行い，PFC::serialize_gains で書き出したゲインを
+//! StaticPFC::from_bytes で読み込む（形式は format モジュールを参照）．
+//! FPUのないマイコンでは fixed モジュールの固定小数点数をスカラー型に使う．
+
+#![no_std]
+
+pub mod fixed;
+pub mod format;
+
+use core::ops::Neg;
+use nalgebra::{ClosedAdd, ClosedDiv, ClosedMul, ClosedSub, SMatrix, SVector, Scalar};
+use num_traits::{One, Zero};
+
+/// 制御入力に掛かっている制約
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ActiveConstraint {
+    None,       // 制約なし
+    Lower,      // 入力下限
+    Upper,      // 入力上限
+    RateLower,  // 入力変化率の下限
+    RateUpper,  // 入力変化率の上限
+    Output,     // 出力制約
+    State,      // 状態制約
+}
+
+/// 実行時に必要な演算だけを持つスカラー型
+///
+/// 平方根や指数関数は使わないので，libm のない環境や固定小数点数でも実装できる．
+pub trait RuntimeScalar: Scalar + Copy + PartialOrd + Zero + One + ClosedAdd + ClosedSub + ClosedMul + ClosedDiv + Neg<Output = Self> {}
+
+impl<T> RuntimeScalar for T where T: Scalar + Copy + PartialOrd + Zero + One + ClosedAdd + ClosedSub + ClosedMul + ClosedDiv + Neg<Output = T> {}
+
+/// 静的サイズの離散時間状態空間モデル
+///
+/// 入力むだ時間は状態に含めておく（N は拡大系の状態数）．
+#[derive(Clone, Copy, Debug)]
+pub struct StaticStateSpace<T: Scalar, const N: usize, const L: usize, const P: usize> {
+    pub a: SMatrix<T, N, N>,  // システム行列
+    pub b: SMatrix<T, N, L>,  // 入力行列
+    pub c: SMatrix<T, P, N>,  // 出力行列
+    pub d: SMatrix<T, P, L>,  // 直達行列
+    pub sample_time: T,       // 離散化周期
+}
+
+impl<T: RuntimeScalar, const N: usize, const L: usize, const P: usize> StaticStateSpace<T, N, L, P> {
+    /// 出力 y = C x
+    pub fn output(&self, x: &SVector<T, N>) -> SVector<T, P> {
+        self.c * x
+    }
+
+    /// 次の状態 A x + B u
+    pub fn next_state(&self, x: &SVector<T, N>, u: &SVector<T, L>) -> SVector<T, N> {
+        self.a * x + self.b * u
+    }
+}
+
+/// 静的サイズのPFCの実行に必要なゲインと内部モデル
+///
+/// 制御則は designer::PFC と同じで u = K_0 (r - y) + Nu_x x_m + Nu_r Δr - d_m．
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StaticGains<T: Scalar, const N: usize, const L: usize, const P: usize, const H: usize> {
+    pub a_m: SMatrix<T, N, N>,   // 内部モデルのシステム行列（むだ時間を含む拡大系）
+    pub b_m: SMatrix<T, N, L>,   // 内部モデルの入力行列
+    pub c_m: SMatrix<T, P, N>,   // 内部モデルの出力行列
+    pub k_0: SMatrix<T, L, P>,   // 偏差に対するゲイン
+    pub nu_x: SMatrix<T, L, N>,  // 内部モデル状態に対するゲイン
+    pub nu_r: SMatrix<T, L, H>,  // 一致点での目標値の変化分に対するゲイン
+    pub horizons: [(usize, u32); H],  // 各一致点の (出力チャネル, むだ時間を除いたサンプル時刻)
+    pub delay: u32,              // 入力むだ時間[サンプル]
+    pub observer: Option<(SMatrix<T, N, P>, SMatrix<T, L, P>)>,  // オフセットフリーモードのオブザーバゲイン（状態, 外乱）
+    pub stabilizer: Option<SMatrix<T, N, P>>,  // 内部モデルの不安定部分を安定化するゲイン
+}
+
+/// 静的サイズのPFC
+///
+/// N: 内部モデルの状態数，L: 入力数，P: 出力数，H: 一致点の総数．
+/// 入力制約と変化率制約に対応する（出力・状態制約は扱わない）．
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug)]
+pub struct StaticPFC<T: Scalar, const N: usize, const L: usize, const P: usize, const H: usize> {
+    gains: StaticGains<T, N, L, P, H>,
+    x_m: SVector<T, N>,  // 内部モデルの状態変数
+    d_m: SVector<T, L>,  // 入力外乱の推定値
+    u_prev: SVector<T, L>,  // 前回の制御入力
+    active: [ActiveConstraint; L],  // 前回の更新で掛かった制約
+    pub limit: [[T; 2]; L],
+    pub rate_limit: Option<[[T; 2]; L]>,  // 1サンプルあたりの入力変化量の制約 \[下限, 上限\]
+}
+
+impl<T: RuntimeScalar, const N: usize, const L: usize, const P: usize, const H: usize> StaticPFC<T, N, L, P, H> {
+    /// * gains: ホスト側で設計したゲインと内部モデル
+    /// * limit: 入力チャネル毎の制御入力制約　\[下限, 上限\]
+    pub fn new(gains: StaticGains<T, N, L, P, H>, limit: [[T; 2]; L]) -> Self {
+        Self {
+            gains,
+            x_m: SVector::zeros(),
+            d_m: SVector::zeros(),
+            u_prev: SVector::zeros(),
+            active: [ActiveConstraint::None; L],
+            limit,
+            rate_limit: None,
+        }
+    }
+
+    /// ゲインと内部モデルを返す．
+    pub fn gains(&self) -> &StaticGains<T, N, L, P, H> {
+        &self.gains
+    }
+
+    /// 入力外乱の推定値を返す（オフセットフリーモード以外では常に0）．
+    pub fn disturbance(&self) -> &SVector<T, L> {
+        &self.d_m
+    }
+
+    /// 直前のupdateで各入力チャネルに掛かった制約を返す．
+    pub fn active_constraint(&self) -> &[ActiveConstraint; L] {
+        &self.active
+    }
+
+    /// 制御入力を計算して内部モデルを更新する．
+    ///
+    /// --- Arguments ---
+    /// * r: 目標値
+    /// * y: 制御対象出力
+    ///
+    /// ---- Return -----
+    /// * u: 制約を考慮した制御入力
+    pub fn update(&mut self, r: &SVector<T, P>, y: &SVector<T, P>) -> SVector<T, L> {
+        self.update_with_preview(core::slice::from_ref(r), y)
+    }
+
+    /// 将来の目標値を使って制御入力を計算し，内部モデルを更新する．
+    ///
+    /// --- Arguments ---
+    /// * r: 現在時刻からの目標値の系列（系列の終端以降は最後の値を保持．空なら前回の入力を保持）
+    /// * y: 制御対象出力
+    ///
+    /// ---- Return -----
+    /// * u: 制約を考慮した制御入力
+    pub fn update_with_preview(&mut self, r: &[SVector<T, P>], y: &SVector<T, P>) -> SVector<T, L> {
+        let g = &self.gains;
+
+        // 不安定部分の安定化に使う出力誤差
+        let e_u = y - g.c_m * self.x_m;
+
+        // オフセットフリーモードでは観測値で内部モデル状態と外乱の推定値を修正する
+        if let Some((l_x, l_d)) = &g.observer {
+            let e = y - g.c_m * self.x_m;
+            self.x_m += l_x * e;
+            self.d_m += l_d * e;
+        }
+
+        let u = if r.is_empty() {
+            // 目標値が与えられなければ前回の入力を保持する（内部モデルの更新は続ける）
+            self.active = [ActiveConstraint::None; L];
+            self.u_prev
+        } else {
+            self.control_input(r, y)
+        };
+
+        // 実際に印加した入力で内部モデル更新
+        let g = &self.gains;
+        self.x_m = g.a_m * self.x_m + g.b_m * (u + self.d_m);
+        if let (None, Some(stabilizer)) = (&g.observer, &g.stabilizer) {
+            self.x_m += stabilizer * e_u;
+        }
+        self.u_prev = u;
+        u
+    }
+
+    /// 目標値と制御対象出力から，制約を考慮した制御入力を計算する．
+    ///
+    /// --- Arguments ---
+    /// * r: 現在時刻からの目標値の系列（空でないこと）
+    /// * y: 制御対象出力
+    fn control_input(&mut self, r: &[SVector<T, P>], y: &SVector<T, P>) -> SVector<T, L> {
+        let g = &self.gains;
+
+        // むだ時間後の目標値を起点とし，各一致点までの目標値の変化分を求める
+        let at = |j: u32| &r[(j as usize).min(r.len() - 1)];
+        let r_0 = at(g.delay);
+        let mut delta_r = SVector::<T, H>::zeros();
+        for (row, &(i, h)) in g.horizons.iter().enumerate() {
+            delta_r[row] = at(g.delay + h)[i] - r_0[i];
+        }
+
+        let mut u = g.k_0 * (r_0 - y) + g.nu_x * self.x_m + g.nu_r * delta_r - self.d_m;
+
+        self.active = [ActiveConstraint::None; L];
+        for j in 0..L {
+            // 変化率制約
+            if let Some(rate_limit) = &self.rate_limit {
+                let lower = self.u_prev[j] + rate_limit[j][0];
+                let upper = self.u_prev[j] + rate_limit[j][1];
+                if u[j] < lower {
+                    u[j] = lower;
+                    self.active[j] = ActiveConstraint::RateLower;
+                } else if u[j] > upper {
+                    u[j] = upper;
+                    self.active[j] = ActiveConstraint::RateUpper;
+                }
+            }
+
+            // 入力制約（変化率制約と両立しない場合はこちらを優先する）
+            if u[j] < self.limit[j][0] {
+                u[j] = self.limit[j][0];
+                self.active[j] = ActiveConstraint::Lower;
+            } else if u[j] > self.limit[j][1] {
+                u[j] = self.limit[j][1];
+                self.active[j] = ActiveConstraint::Upper;
+            }
+        }
+        u
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// テスト用のゲイン（N = 2, L = 1, P = 1, H = 2，オフセットフリーモード）
+    pub(crate) fn example_gains() -> StaticGains<f64, 2, 1, 1, 2> {
+        StaticGains {
+            a_m: SMatrix::from_row_slice(&[0.9, 0.1, 0.0, 0.8]),
+            b_m: SMatrix::from_row_slice(&[0.0, 0.1]),
+            c_m: SMatrix::from_row_slice(&[1.0, 0.0]),
+            k_0: SMatrix::from_row_slice(&[0.5]),
+            nu_x: SMatrix::from_row_slice(&[-0.2, 0.3]),
+            nu_r: SMatrix::from_row_slice(&[0.4, 0.6]),
+            horizons: [(0, 2), (0, 5)],
+            delay: 1,
+            observer: Some((SMatrix::from_row_slice(&[0.1, 0.2]), SMatrix::from_row_slice(&[0.3]))),
+            stabilizer: None,
+        }
+    }
+
+    #[test]
+    fn empty_setpoint_holds_previous_input() {
+        let mut pfc = StaticPFC::new(example_gains(), [[-1.0, 1.0]]);
+        let y = SVector::<f64, 1>::new(0.05);
+        let u = pfc.update(&SVector::from_element(0.1), &y);
+        assert_eq!(pfc.update_with_preview(&[], &y), u);
+        assert_eq!(pfc.active_constraint(), &[ActiveConstraint::None]);
+    }
+
+    #[test]
+    fn limits_are_applied_in_order() {
+        let mut pfc = StaticPFC::new(example_gains(), [[-1.0, 0.04]]);
+        pfc.rate_limit = Some([[-0.03, 0.03]]);
+        // 変化率制約で 0.03 に抑えられる
+        let u = pfc.update(&SVector::from_element(1.0), &SVector::zeros());
+        assert_eq!(u[0], 0.03);
+        assert_eq!(pfc.active_constraint(), &[ActiveConstraint::RateUpper]);
+        // 変化率制約では 0.06 まで許されるが入力制約で 0.04 に抑えられる
+        let u = pfc.update(&SVector::from_element(1.0), &SVector::zeros());
+        assert_eq!(u[0], 0.04);
+        assert_eq!(pfc.active_constraint(), &[ActiveConstraint::Upper]);
+    }
+}
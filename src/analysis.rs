@@ -0,0 +1,444 @@
+//! 制約なしのPFCを含む閉ループ系の解析
+
+use std::f64::consts::PI;
+use nalgebra::Complex;
+
+use super::{DMatrix, DVector, StateSpace};
+use super::controller::{Controller, Setpoint};
+use super::designer::PFC;
+use super::error::Error;
+
+/// 閉ループ系の解析結果
+pub struct ClosedLoop {
+    /// 目標値 r から制御対象出力 y までの閉ループ系
+    ///
+    /// 状態は \[制御対象の状態（むだ時間分の入力を含む）; コントローラの状態\]．
+    pub reference: StateSpace<f64>,
+    /// 感度関数 S（出力外乱から出力まで）
+    pub sensitivity: StateSpace<f64>,
+    /// 相補感度関数 T = I - S（観測ノイズから出力までの符号を反転したもの）
+    pub complementary: StateSpace<f64>,
+    /// 閉ループ極
+    pub poles: Vec<Complex<f64>>,
+    /// 目標値から出力までの直流ゲイン
+    pub dc_gain: DMatrix<f64>,
+}
+
+impl ClosedLoop {
+    /// 全ての閉ループ極が単位円の内側にあるか
+    pub fn is_stable(&self) -> bool {
+        self.poles.iter().all(|p| p.norm_sqr() < 1.0)
+    }
+}
+
+/// 制御対象と制約なしのPFCで閉ループ系を作って解析する．
+///
+/// PFCは等価な線形コントローラ（PFC::linear_controller）として扱うので，
+/// 入力制約などの非線形な要素は考慮しない．
+///
+///  ------- Arguments -------
+///  * plant: 離散時間状態空間モデル（直達項なし．むだ時間を含んでよい）
+///  * pfc: 解析するPFC
+pub fn closed_loop(plant: &StateSpace<f64>, pfc: &PFC) -> Result<ClosedLoop, Error> {
+    let (plant, ctrl) = loop_components(plant, pfc)?;
+    let n_p = plant.a.nrows();
+    let n_c = ctrl.a.nrows();
+    let p = plant.c.nrows();
+
+    let b_r = ctrl.b.columns(0, p);
+    let b_y = ctrl.b.columns(p, p);
+    let d_r = ctrl.d.columns(0, p);
+    let d_y = ctrl.d.columns(p, p);
+
+    // u = C_c z + D_r r + D_y (C_p x + w)  （w: 出力外乱）
+    let n = n_p + n_c;
+    let mut a = DMatrix::<f64>::zeros(n, n);
+    a.slice_mut((0, 0), (n_p, n_p)).copy_from(&(&plant.a + &plant.b * d_y * &plant.c));
+    a.slice_mut((0, n_p), (n_p, n_c)).copy_from(&(&plant.b * &ctrl.c));
+    a.slice_mut((n_p, 0), (n_c, n_p)).copy_from(&(b_y * &plant.c));
+    a.slice_mut((n_p, n_p), (n_c, n_c)).copy_from(&ctrl.a);
+
+    let mut b_ref = DMatrix::<f64>::zeros(n, p);
+    b_ref.slice_mut((0, 0), (n_p, p)).copy_from(&(&plant.b * d_r));
+    b_ref.slice_mut((n_p, 0), (n_c, p)).copy_from(&b_r);
+
+    let mut b_dist = DMatrix::<f64>::zeros(n, p);
+    b_dist.slice_mut((0, 0), (n_p, p)).copy_from(&(&plant.b * d_y));
+    b_dist.slice_mut((n_p, 0), (n_c, p)).copy_from(&b_y);
+
+    let mut c = DMatrix::<f64>::zeros(p, n);
+    c.slice_mut((0, 0), (p, n_p)).copy_from(&plant.c);
+
+    let eye_p = DMatrix::<f64>::identity(p, p);
+    let reference = StateSpace::new(a.clone(), b_ref, c.clone(), DMatrix::zeros(p, p), plant.sample_time)?;
+    let sensitivity = StateSpace::new(a.clone(), b_dist.clone(), c.clone(), eye_p.clone(), plant.sample_time)?;
+    let complementary = StateSpace::new(a.clone(), b_dist, -c, DMatrix::zeros(p, p), plant.sample_time)?;
+
+    let poles = a.complex_eigenvalues().iter().cloned().collect();
+    let dc_gain = dc_gain(&reference);
+
+    Ok(ClosedLoop {reference, sensitivity, complementary, poles, dc_gain})
+}
+
+/// 制御対象の入力で切った開ループ伝達関数 L(z) = -K_y(z) P(z) を作る．
+///
+/// K_y は等価な線形コントローラの出力 y から u までの部分で，負帰還の慣例に合わせて
+/// 符号を反転している（閉ループ系の特性方程式は det(I + L) = 0）．
+///
+///  ------- Arguments -------
+///  * plant: 離散時間状態空間モデル（直達項なし．むだ時間を含んでよい）
+///  * pfc: 解析するPFC
+pub fn open_loop(plant: &StateSpace<f64>, pfc: &PFC) -> Result<StateSpace<f64>, Error> {
+    let (plant, ctrl) = loop_components(plant, pfc)?;
+    let n_p = plant.a.nrows();
+    let n_c = ctrl.a.nrows();
+    let l = plant.b.ncols();
+    let p = plant.c.nrows();
+
+    let b_y = ctrl.b.columns(p, p);
+    let d_y = ctrl.d.columns(p, p);
+
+    let n = n_p + n_c;
+    let mut a = DMatrix::<f64>::zeros(n, n);
+    a.slice_mut((0, 0), (n_p, n_p)).copy_from(&plant.a);
+    a.slice_mut((n_p, 0), (n_c, n_p)).copy_from(&(b_y * &plant.c));
+    a.slice_mut((n_p, n_p), (n_c, n_c)).copy_from(&ctrl.a);
+    let mut b = DMatrix::<f64>::zeros(n, l);
+    b.slice_mut((0, 0), (n_p, l)).copy_from(&plant.b);
+    let mut c = DMatrix::<f64>::zeros(l, n);
+    c.slice_mut((0, 0), (l, n_p)).copy_from(&-(d_y * &plant.c));
+    c.slice_mut((0, n_p), (l, n_c)).copy_from(&-&ctrl.c);
+
+    StateSpace::new(a, b, c, DMatrix::zeros(l, l), plant.sample_time)
+}
+
+/// 入力チャネルを1つだけ切った開ループ L_j(z) を作る（loop-at-a-time）．
+///
+/// 入力チャネルjのループだけを切り，他の入力チャネルのループは閉じたままにした
+/// 1入力1出力の開ループで，1入力1出力の場合は open_loop と同じ．
+///
+///  ------- Arguments -------
+///  * plant: 離散時間状態空間モデル（直達項なし．むだ時間を含んでよい）
+///  * pfc: 解析するPFC
+///  * channel: 切る入力チャネル
+pub fn loop_at_a_time(plant: &StateSpace<f64>, pfc: &PFC, channel: usize) -> Result<StateSpace<f64>, Error> {
+    let loop_tf = open_loop(plant, pfc)?;
+    loop_channel(&loop_tf, channel)
+}
+
+/// 開ループ L の入力チャネルj以外を v_k = -w_k で閉じた L_j を作る．
+fn loop_channel(loop_tf: &StateSpace<f64>, channel: usize) -> Result<StateSpace<f64>, Error> {
+    let l = loop_tf.b.ncols();
+    if channel >= l {
+        return Err(Error::DimensionMismatch("channel index is out of range"));
+    }
+    // L は直達項がないので A_j = A - Σ_{k≠j} B_k C_k
+    let mut a = loop_tf.a.clone();
+    for k in (0..l).filter(|&k| k != channel) {
+        a -= loop_tf.b.column(k) * loop_tf.c.row(k);
+    }
+    let b = loop_tf.b.columns(channel, 1).into_owned();
+    let c = loop_tf.c.rows(channel, 1).into_owned();
+    StateSpace::new(a, b, c, DMatrix::zeros(1, 1), loop_tf.sample_time)
+}
+
+/// 入力チャネル毎に切った開ループの安定余裕
+#[derive(Clone, Copy, Debug)]
+pub struct Margins {
+    /// ゲイン余裕[倍]（位相交差がなければ無限大）
+    pub gain_margin: f64,
+    /// 位相交差周波数[rad/s]
+    pub phase_crossover: f64,
+    /// 位相余裕[deg]（ゲイン交差がなければ無限大）
+    pub phase_margin: f64,
+    /// ゲイン交差周波数[rad/s]
+    pub gain_crossover: f64,
+    /// むだ時間余裕[s]（閉ループ系を不安定にする最小の追加むだ時間）
+    pub delay_margin: f64,
+    /// 感度関数のピーク値 max |1 / (1 + L)|
+    pub peak_sensitivity: f64,
+}
+
+/// 周波数応答を計算する周波数点の数
+const N_FREQUENCY: usize = 2000;
+
+/// 交差周波数を二分法で求めるときの反復回数
+const N_BISECTION: usize = 60;
+
+/// 制御対象の入力で切った開ループのゲイン余裕・位相余裕・むだ時間余裕と感度関数のピーク値を求める．
+///
+/// 多入力の場合は入力チャネル毎に，他のチャネルのループを閉じたまま
+/// そのチャネルだけを切った開ループ（loop_at_a_time）の余裕を求める．
+/// 全チャネルを同時にずらした場合の余裕ではないので，多入力多出力系のロバスト性は
+/// 感度関数の最大特異値のピーク値 peak_gain(&closed_loop(..)?.sensitivity) でも確認すること．
+///
+/// 周波数はナイキスト周波数の1e-5倍からナイキスト周波数までを
+/// 対数等間隔に評価し，交差周波数は二分法で求める．
+/// 複数の交差周波数がある場合はそれぞれ最も小さい余裕を返す．
+///
+///  ------- Arguments -------
+///  * plant: 離散時間状態空間モデル（直達項なし．むだ時間を含んでよい）
+///  * pfc: 解析するPFC
+///
+///  -------- Return ---------
+///  * 入力チャネル毎の安定余裕
+pub fn margins(plant: &StateSpace<f64>, pfc: &PFC) -> Result<Vec<Margins>, Error> {
+    let loop_tf = open_loop(plant, pfc)?;
+    (0..loop_tf.b.ncols())
+        .map(|j| Ok(siso_margins(&loop_channel(&loop_tf, j)?)))
+        .collect()
+}
+
+/// 1入力1出力の開ループの安定余裕を求める．
+fn siso_margins(loop_tf: &StateSpace<f64>) -> Margins {
+    let eval = |w: f64| frequency_response(loop_tf, w)[(0, 0)];
+
+    let w_nyquist = PI / loop_tf.sample_time;
+    let omega = log_space(w_nyquist * 1e-5, w_nyquist, N_FREQUENCY);
+    let response: Vec<Complex<f64>> = omega.iter().map(|&w| eval(w)).collect();
+
+    let mut margins = Margins {
+        gain_margin: f64::INFINITY,
+        phase_crossover: f64::NAN,
+        phase_margin: f64::INFINITY,
+        gain_crossover: f64::NAN,
+        delay_margin: f64::INFINITY,
+        peak_sensitivity: response.iter().map(|l| 1.0 / (l + 1.0).norm_sqr().sqrt()).fold(0.0, f64::max),
+    };
+    for k in 0..(omega.len() - 1) {
+        let (l_0, l_1) = (response[k], response[k + 1]);
+
+        // 位相交差（負の実軸を横切る点）
+        if l_0.im * l_1.im <= 0.0 && l_0.im != l_1.im {
+            let w = bisect(omega[k], omega[k + 1], |w| eval(w).im);
+            let l = eval(w);
+            if l.re < 0.0 {
+                let gm = 1.0 / l.re.abs();
+                if gm < margins.gain_margin {
+                    margins.gain_margin = gm;
+                    margins.phase_crossover = w;
+                }
+            }
+        }
+
+        // ゲイン交差（単位円を横切る点）
+        let (g_0, g_1) = (l_0.norm_sqr().sqrt() - 1.0, l_1.norm_sqr().sqrt() - 1.0);
+        if g_0 * g_1 <= 0.0 && g_0 != g_1 {
+            let w = bisect(omega[k], omega[k + 1], |w| eval(w).norm_sqr().sqrt() - 1.0);
+            // 負の実軸から測った位相[rad]（-π〜π）
+            let l = -eval(w);
+            let pm = l.im.atan2(l.re);
+            if pm.abs().to_degrees() < margins.phase_margin.abs() {
+                margins.phase_margin = pm.to_degrees();
+                margins.gain_crossover = w;
+            }
+            // 追加むだ時間 τ による位相遅れ ωτ がちょうど位相余裕を使い切る
+            let dm = if pm >= 0.0 {pm / w} else {(pm + 2.0 * PI) / w};
+            margins.delay_margin = margins.delay_margin.min(dm);
+        }
+    }
+    margins
+}
+
+/// 離散時間状態空間モデルの周波数応答 G(e^{jωT}) = C (e^{jωT} I - A)^-1 B + D
+///
+/// 単位円上に極がある周波数では全要素が無限大になる．
+///
+///  ------- Arguments -------
+///  * sys: 離散時間状態空間モデル
+///  * omega: 角周波数[rad/s]
+pub fn frequency_response(sys: &StateSpace<f64>, omega: f64) -> DMatrix<Complex<f64>> {
+    let n = sys.a.nrows();
+    let theta = omega * sys.sample_time;
+    let z = Complex::new(theta.cos(), theta.sin());
+    let z_a = DMatrix::<Complex<f64>>::from_diagonal_element(n, n, z) - sys.a.map(|v| Complex::new(v, 0.0));
+    match z_a.lu().solve(&sys.b.map(|v| Complex::new(v, 0.0))) {
+        Some(x) => sys.c.map(|v| Complex::new(v, 0.0)) * x + sys.d.map(|v| Complex::new(v, 0.0)),
+        None => DMatrix::from_element(sys.c.nrows(), sys.b.ncols(), Complex::new(f64::INFINITY, 0.0)),
+    }
+}
+
+/// ボード線図のデータを計算する．
+///
+///  ------- Arguments -------
+///  * sys: 離散時間状態空間モデル
+///  * omega: 角周波数[rad/s]（昇順）
+///  * output: 出力チャネル
+///  * input: 入力チャネル
+///
+///  -------- Return ---------
+///  * 各周波数の (ゲイン[dB], 位相[deg])．位相は連続になるようにアンラップする．
+pub fn bode(sys: &StateSpace<f64>, omega: &[f64], output: usize, input: usize) -> Result<Vec<(f64, f64)>, Error> {
+    let response = nyquist(sys, omega, output, input)?;
+    let mut bode = Vec::with_capacity(response.len());
+    let mut phase_prev = 0.0;
+    for (k, g) in response.iter().enumerate() {
+        let mut phase = g.im.atan2(g.re).to_degrees();
+        if k > 0 {
+            phase -= 360.0 * ((phase - phase_prev) / 360.0).round();
+        }
+        phase_prev = phase;
+        bode.push((20.0 * g.norm_sqr().sqrt().log10(), phase));
+    }
+    Ok(bode)
+}
+
+/// ナイキスト線図のデータ（周波数応答の複素数値）を計算する．
+///
+///  ------- Arguments -------
+///  * sys: 離散時間状態空間モデル
+///  * omega: 角周波数[rad/s]
+///  * output: 出力チャネル
+///  * input: 入力チャネル
+pub fn nyquist(sys: &StateSpace<f64>, omega: &[f64], output: usize, input: usize) -> Result<Vec<Complex<f64>>, Error> {
+    if sys.sample_time == 0.0 {
+        return Err(Error::NotDiscrete);
+    }
+    if output >= sys.c.nrows() || input >= sys.b.ncols() {
+        return Err(Error::DimensionMismatch("channel index is out of range"));
+    }
+    Ok(omega.iter().map(|&w| frequency_response(sys, w)[(output, input)]).collect())
+}
+
+/// 対数等間隔の周波数列[rad/s]を作る．
+///
+///  ------- Arguments -------
+///  * w_min: 最小周波数[rad/s]
+///  * w_max: 最大周波数[rad/s]
+///  * n: 点数（2以上）
+pub fn log_space(w_min: f64, w_max: f64, n: usize) -> Vec<f64> {
+    let ratio = (w_max / w_min).ln();
+    (0..n).map(|k| w_min * (ratio * k as f64 / (n - 1) as f64).exp()).collect()
+}
+
+/// f(a) と f(b) の符号が異なる区間 \[a, b\] で f の零点を二分法で求める．
+fn bisect(mut a: f64, mut b: f64, f: impl Fn(f64) -> f64) -> f64 {
+    let f_a = f(a);
+    for _ in 0..N_BISECTION {
+        let mid = 0.5 * (a + b);
+        if f(mid) * f_a > 0.0 {
+            a = mid;
+        } else {
+            b = mid;
+        }
+    }
+    0.5 * (a + b)
+}
+
+/// 零状態から一定の目標値で制約込みの閉ループ系をシミュレーションする．
+///
+/// 制御器は複製して使うので，内部状態は渡した時点のものから始まる．
+///
+///  ------- Arguments -------
+///  * controller: シミュレーションする制御器（PFCなど）
+///  * sys: 離散時間状態空間モデル（むだ時間を含んでよい）
+///  * r: 目標値
+///  * n_steps: シミュレーションするサンプル数
+///
+///  -------- Return ---------
+///  * 各サンプルの (出力, 制御入力) の列
+pub fn simulate_step<C: Controller + Clone>(controller: &C, sys: &StateSpace<f64>, r: &DVector<f64>, n_steps: usize) -> (Vec<DVector<f64>>, Vec<DVector<f64>>) {
+    let mut controller = controller.clone();
+    let setpoint = Setpoint::Constant(r);
+    let sys = sys.delay_augmented();
+    let mut x = DVector::zeros(sys.a.nrows());
+    let mut y = Vec::with_capacity(n_steps);
+    let mut u = Vec::with_capacity(n_steps);
+    for _ in 0..n_steps {
+        let y_k = &sys.c * &x;
+        let u_k = controller.update(&setpoint, &y_k);
+        x = &sys.a * &x + &sys.b * &u_k;
+        y.push(y_k);
+        u.push(u_k);
+    }
+    (y, u)
+}
+
+/// 周波数応答の最大特異値の最大値（H∞ノルムの近似）
+///
+/// 周波数はナイキスト周波数の1e-5倍からナイキスト周波数までを対数等間隔に評価する．
+pub fn peak_gain(sys: &StateSpace<f64>) -> f64 {
+    let w_nyquist = PI / sys.sample_time;
+    log_space(w_nyquist * 1e-5, w_nyquist, N_FREQUENCY).iter()
+        .map(|&w| frequency_response(sys, w).singular_values().max())
+        .fold(0.0, f64::max)
+}
+
+/// 閉ループ系を作るための共通の確認と準備
+///
+/// むだ時間を状態に含めた制御対象と，PFCと等価な線形コントローラを返す．
+fn loop_components(plant: &StateSpace<f64>, pfc: &PFC) -> Result<(StateSpace<f64>, StateSpace<f64>), Error> {
+    if plant.sample_time == 0.0 {
+        return Err(Error::NotDiscrete);
+    }
+    let ctrl = pfc.linear_controller();
+    if plant.sample_time != ctrl.sample_time {
+        return Err(Error::InvalidSampleTime);
+    }
+    if plant.d.iter().any(|&v| v != 0.0) {
+        return Err(Error::InvalidParameter("plant with direct feedthrough is not supported"));
+    }
+
+    let plant = plant.delay_augmented();
+    if ctrl.b.ncols() != 2 * plant.c.nrows() || ctrl.c.nrows() != plant.b.ncols() {
+        return Err(Error::DimensionMismatch("controller does not match the plant"));
+    }
+    Ok((plant, ctrl))
+}
+
+/// 離散時間状態空間モデルの直流ゲイン C (I - A)^-1 B + D
+///
+/// z = 1 に極がある場合は全要素が無限大の行列を返す．
+pub fn dc_gain(sys: &StateSpace<f64>) -> DMatrix<f64> {
+    let n = sys.a.nrows();
+    match (DMatrix::<f64>::identity(n, n) - &sys.a).lu().solve(&sys.b) {
+        Some(x) => &sys.c * x + &sys.d,
+        None => DMatrix::from_element(sys.c.nrows(), sys.b.ncols(), f64::INFINITY),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::designer::{InputChannel, OutputChannel};
+    use crate::test_plants::{mass_spring_damper, two_mass};
+
+    /// 入力チャネルchannelのゲインをgain倍した制御対象
+    fn scale_input(plant: &StateSpace<f64>, channel: usize, gain: f64) -> StateSpace<f64> {
+        let mut b = plant.b.clone();
+        b.column_mut(channel).scale_mut(gain);
+        StateSpace::new(plant.a.clone(), b, plant.c.clone(), plant.d.clone(), plant.sample_time).unwrap().with_delay(plant.delay)
+    }
+
+    /// 各入力チャネルのゲイン余裕の少し内側では安定，少し外側では不安定になることを確認する
+    fn check_gain_margins(plant: &StateSpace<f64>, pfc: &PFC) {
+        let margins = margins(plant, pfc).unwrap();
+        assert_eq!(margins.len(), plant.b.ncols());
+        for (j, m) in margins.iter().enumerate() {
+            assert!(m.gain_margin.is_finite() && m.gain_margin > 1.0, "channel {}: {:?}", j, m);
+            assert!(m.phase_margin > 0.0 && m.delay_margin > 0.0, "channel {}: {:?}", j, m);
+            assert!(closed_loop(&scale_input(plant, j, 0.97 * m.gain_margin), pfc).unwrap().is_stable());
+            assert!(!closed_loop(&scale_input(plant, j, 1.03 * m.gain_margin), pfc).unwrap().is_stable());
+        }
+    }
+
+    #[test]
+    fn siso_gain_margin_matches_closed_loop() {
+        let plant = mass_spring_damper(5.0, 5.0, 5.0).with_delay(3);
+        let pfc = PFC::new(&plant, &[InputChannel::new([-5.0, 5.0])], &[OutputChannel::new(0.5)]).unwrap();
+        check_gain_margins(&plant, &pfc);
+    }
+
+    #[test]
+    fn loop_at_a_time_margins_match_closed_loop() {
+        let plant = two_mass().with_delay(3);
+        let pfc = PFC::new(&plant, &[InputChannel::new([-50.0, 50.0]); 2], &[OutputChannel::new(1.0), OutputChannel::new(1.5)]).unwrap();
+        check_gain_margins(&plant, &pfc);
+        assert!(matches!(loop_at_a_time(&plant, &pfc, 2), Err(Error::DimensionMismatch(_))));
+
+        // 多入力多出力系のロバスト性の指標
+        let sensitivity = closed_loop(&plant, &pfc).unwrap().sensitivity;
+        let peak = peak_gain(&sensitivity);
+        assert!(peak.is_finite() && peak >= 1.0, "peak = {}", peak);
+    }
+}
@@ -0,0 +1,162 @@
+//! PFCで制御入力を表す基底関数
+
+use super::DMatrix;
+use super::error::Error;
+
+/// 基底関数の種類
+///
+/// 0番目の基底関数はどの種類でも（近似的に）一定値になるようにしてあり，
+/// 定常状態の入力を表現できる．
+#[derive(Clone, Copy, Debug)]
+pub enum Basis {
+    /// 多項式 B_l(q) = q^l
+    Polynomial,
+    /// 指数関数 B_l(q) = λ^(l q)（0 < λ < 1，l = 1以降が減衰率λ^lで減衰する）
+    Exponential(f64),
+    /// ステップ B_0(q) = 1 と極aの離散Laguerre関数 B_l(q) = L_l(q)（0 ≦ a < 1）
+    Laguerre(f64),
+    /// 任意の関数 B_l(q) = f(l, q)
+    Custom(fn(usize, u32) -> f64),
+}
+
+impl Basis {
+    /// 基底関数のパラメータが範囲内か確認する．
+    pub fn validate(&self) -> Result<(), Error> {
+        match *self {
+            Basis::Exponential(lambda) if !(lambda > 0.0 && lambda < 1.0) => {
+                Err(Error::InvalidParameter("decay rate of the exponential basis must be in (0, 1)"))
+            },
+            Basis::Laguerre(a) if !(0.0..1.0).contains(&a) => {
+                Err(Error::InvalidParameter("pole of the Laguerre basis must be in [0, 1)"))
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// 基底関数の値を計算する．
+    ///
+    ///  ------- Arguments -------
+    ///  * n_b: 基底関数の個数
+    ///  * n_q: 計算するサンプル数
+    ///
+    ///  -------- Return ---------
+    ///  * (l, q) 要素が B_l(q) の行列（n_b × n_q）
+    ///
+    /// パラメータが範囲外なら validate と同じエラーを，
+    /// 任意の関数が非有限値を返した場合は Error::InvalidParameter を返す．
+    pub fn values(&self, n_b: usize, n_q: u32) -> Result<DMatrix<f64>, Error> {
+        self.validate()?;
+        let mut values = DMatrix::<f64>::zeros(n_b, n_q as usize);
+        match *self {
+            Basis::Polynomial => {
+                for l in 0..n_b {
+                    for q in 0..n_q {
+                        values[(l, q as usize)] = (q as f64).powi(l as i32);
+                    }
+                }
+            },
+            Basis::Exponential(lambda) => {
+                for l in 0..n_b {
+                    for q in 0..n_q {
+                        values[(l, q as usize)] = lambda.powi((l as u32 * q) as i32);
+                    }
+                }
+            },
+            Basis::Laguerre(a) => {
+                for q in 0..n_q as usize {
+                    values[(0, q)] = 1.0;
+                }
+                // L(q+1) = A_l L(q), L(0) = sqrt(1 - a^2) [1, -a, a^2, ...]
+                let n_l = n_b - 1;
+                if n_l > 0 {
+                    let beta = 1.0 - a * a;
+                    let mut a_l = DMatrix::<f64>::zeros(n_l, n_l);
+                    for i in 0..n_l {
+                        a_l[(i, i)] = a;
+                        for j in 0..i {
+                            a_l[(i, j)] = (-a).powi((i - j - 1) as i32) * beta;
+                        }
+                    }
+                    let mut l_q = DMatrix::<f64>::from_fn(n_l, 1, |i, _| beta.sqrt() * (-a).powi(i as i32));
+                    for q in 0..n_q as usize {
+                        values.slice_mut((1, q), (n_l, 1)).copy_from(&l_q);
+                        l_q = &a_l * l_q;
+                    }
+                }
+            },
+            Basis::Custom(f) => {
+                for l in 0..n_b {
+                    for q in 0..n_q {
+                        values[(l, q as usize)] = f(l, q);
+                    }
+                }
+                if !values.iter().all(|v| v.is_finite()) {
+                    return Err(Error::InvalidParameter("custom basis function returned a non-finite value"));
+                }
+            },
+        }
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DVector;
+    use crate::designer::{Coincidence, InputChannel, OutputChannel, PFC};
+    use crate::test_plants::{mass_spring_damper, simulate};
+
+    /// 基底関数を指定したPFCをバネ・マス・ダンパ系で動かし，最後の出力を返す
+    fn track_step(basis: Basis) -> f64 {
+        let plant = mass_spring_damper(5.0, 5.0, 5.0);
+        let input = InputChannel {n_b: 3, basis, ..InputChannel::new([-50.0, 50.0])};
+        let output = OutputChannel {coincidence: Coincidence::Uniform(4), ..OutputChannel::new(0.5)};
+        let mut pfc = PFC::new(&plant, &[input], &[output]).unwrap();
+        let r = vec![DVector::from_element(1, 0.1); 400];
+        let log = simulate(&plant, &mut pfc, &r, false, |_| DVector::zeros(1));
+        log.last().unwrap().0[0]
+    }
+
+    #[test]
+    fn values_reject_out_of_range_parameters() {
+        for basis in [Basis::Exponential(0.0), Basis::Exponential(1.0), Basis::Laguerre(-0.1), Basis::Laguerre(1.0), Basis::Laguerre(f64::NAN)] {
+            assert!(matches!(basis.values(3, 10), Err(Error::InvalidParameter(_))), "{:?}", basis);
+        }
+    }
+
+    #[test]
+    fn first_basis_function_is_constant() {
+        for basis in [Basis::Polynomial, Basis::Exponential(0.8), Basis::Laguerre(0.5)] {
+            let values = basis.values(3, 10).unwrap();
+            assert_eq!(values.shape(), (3, 10));
+            assert!(values.row(0).iter().all(|&v| v == 1.0), "{:?}", basis);
+        }
+        let values = Basis::Polynomial.values(3, 4).unwrap();
+        assert_eq!(values.row(2).iter().cloned().collect::<Vec<_>>(), vec![0.0, 1.0, 4.0, 9.0]);
+    }
+
+    #[test]
+    fn non_finite_custom_basis_is_rejected() {
+        let basis = Basis::Custom(|l, q| if l == 1 && q == 3 {f64::NAN} else {1.0});
+        assert!(matches!(basis.values(2, 10), Err(Error::InvalidParameter(_))));
+        let basis = Basis::Custom(|l, q| (q as f64).powi(l as i32) / (q as f64 - 5.0));
+        assert!(matches!(basis.values(2, 10), Err(Error::InvalidParameter(_))));
+
+        // 設計でもパニックせずにエラーになる
+        let plant = mass_spring_damper(5.0, 5.0, 5.0);
+        let input = InputChannel {basis: Basis::Custom(|_, _| f64::NAN), ..InputChannel::new([-50.0, 50.0])};
+        assert!(matches!(PFC::new(&plant, &[input], &[OutputChannel::new(0.5)]), Err(Error::InvalidParameter(_))));
+    }
+
+    #[test]
+    fn exponential_basis_tracks_step() {
+        let y = track_step(Basis::Exponential(0.8));
+        assert!((y - 0.1).abs() < 1e-6, "y = {}", y);
+    }
+
+    #[test]
+    fn laguerre_basis_tracks_step() {
+        let y = track_step(Basis::Laguerre(0.6));
+        assert!((y - 0.1).abs() < 1e-6, "y = {}", y);
+    }
+}
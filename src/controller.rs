@@ -0,0 +1,67 @@
+//! 制御則を差し替えるための共通インターフェース
+
+use super::{DVector, RealField};
+
+/// 目標値の与え方
+///
+/// 各要素は出力チャネル毎の値を並べたベクトル．
+/// 系列・多項式が空の場合，PFCは前回の入力を保持する．
+pub enum Setpoint<'a, T = f64> {
+    /// 一定値
+    Constant(&'a DVector<T>),
+    /// 現在時刻からの目標値の系列 r(k), r(k+1), ...（系列の終端以降は最後の値を保持）
+    Sequence(&'a [DVector<T>]),
+    /// 多項式 r(k+j) = c_0 + c_1 j + c_2 j^2 + ...（jはサンプル数）
+    Polynomial(&'a [DVector<T>]),
+}
+
+impl<T: RealField + Copy> Setpoint<'_, T> {
+    /// 目標値が1つも与えられていないか（空の系列・係数のない多項式）
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Setpoint::Constant(_) => false,
+            Setpoint::Sequence(seq) => seq.is_empty(),
+            Setpoint::Polynomial(coef) => coef.is_empty(),
+        }
+    }
+
+    /// jサンプル後の出力チャネルiの目標値（空でないこと）
+    pub(crate) fn at(&self, j: u32, i: usize) -> T {
+        match self {
+            Setpoint::Constant(r) => r[i],
+            Setpoint::Sequence(seq) => seq[(j as usize).min(seq.len() - 1)][i],
+            Setpoint::Polynomial(coef) => {
+                let mut r = T::zero();
+                for (m, c_m) in coef.iter().enumerate() {
+                    r = nalgebra::convert::<f64, T>(j as f64).powi(m as i32) * c_m[i] + r;
+                }
+                r
+            }
+        }
+    }
+}
+
+/// 離散時間の制御器
+///
+/// シミュレーションや解析のツールはこのトレイトだけを使うので，
+/// PFC・PID・LQR・MPCなどを同じように動かせる．
+pub trait Controller<T = f64> {
+    /// 内部状態を初期状態に戻す（制約などの設定はそのまま）．
+    fn reset(&mut self);
+
+    /// 制御入力を計算して内部状態を更新する．
+    ///
+    /// --- Arguments ---
+    /// * setpoint: 現在時刻以降の目標値（先読みしない制御器は現在の値だけを使う）
+    /// * y: 制御対象出力
+    ///
+    /// ---- Return -----
+    /// * u: 制御入力
+    fn update(&mut self, setpoint: &Setpoint<T>, y: &DVector<T>) -> DVector<T>;
+
+    /// 入力チャネル毎の制御入力制約 \[下限, 上限\]
+    fn input_limits(&self) -> &[[T; 2]];
+
+    /// 内部状態（並びは制御器毎に決める）
+    fn state(&self) -> DVector<T>;
+}
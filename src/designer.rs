@@ -1,115 +1,1776 @@
 //! PFCの設計に関わるものをまとめたモジュール
 
-use super::{DVector, DMatrix, Matrix1xX, StateSpace};
+use std::cmp::Ordering;
 
-pub struct PFC {
-    a_m: DMatrix<f64>,  // 内部モデルのシステム行列
-    b_m: DMatrix<f64>,  // 内部モデルの入力行列
-    x_m: DVector<f64>,  // 内部モデルの状態変数
-    k_0: f64,
-    nu_x_transpose: Matrix1xX<f64>,
-    pub limit: [f64; 2]
+use nalgebra::{Complex, Dynamic, MatrixSlice1xX, RealField, SMatrix, U1};
+use pfc_runtime::{fixed::Fixed, format, StaticGains, StaticPFC};
+
+pub use pfc_runtime::ActiveConstraint;
+
+use super::{DVector, DMatrix, StateSpace};
+use super::basis::Basis;
+use super::controller::{Controller, Setpoint};
+use super::error::Error;
+use super::report::{DesignReport, QuantizationReport};
+use super::trajectory::ReferenceTrajectory;
+
+/// 一致点の決め方
+#[derive(Clone, Debug)]
+pub enum Coincidence {
+    /// 閉ループ応答時間をn_h等分した時刻 floor(t_clrt / (Ts (n_h - j)))，j = 0, ..., n_h-1
+    Uniform(usize),
+    /// 一致点のサンプル時刻を直接与える（1以上で狭義単調増加）
+    Explicit(Vec<u32>),
+    /// 制御対象の支配的な時定数τをn_h等分した時刻 ceil(τ (j + 1) / (Ts n_h))，j = 0, ..., n_h-1
+    DominantTimeConstant(usize),
 }
 
-impl PFC {
+impl Coincidence {
+    /// 一致点のサンプル時刻を求める．
+    ///
+    /// --- Arguments ---
     /// * sys: 離散時間状態空間モデル
-    /// * n_b: 基底関数の個数
-    /// * n_h: 一致点の個数
     /// * t_clrt: 閉ループ応答時間
-    /// * limit: 制御入力制約　\[下限, 上限\]
-    pub fn new(sys: &StateSpace<f64>, n_b: usize, n_h: usize, t_clrt: f64, limit: [f64; 2]) -> Self {
+    fn resolve<T: RealField + Copy>(&self, sys: &StateSpace<T>, t_clrt: T) -> Result<Vec<u32>, Error> {
+        let points = match self {
+            Coincidence::Uniform(n_h) => {
+                if !t_clrt.is_finite() || t_clrt <= T::zero() {
+                    return Err(Error::InvalidParameter("closed-loop response time must be positive"));
+                }
+                coincidence_points(sys.sample_time, *n_h, t_clrt)
+            },
+            Coincidence::Explicit(points) => points.clone(),
+            Coincidence::DominantTimeConstant(n_h) => {
+                // 安定な固有値のうち最も絶対値が大きいものが支配的な極
+                let lambda = sys.a.complex_eigenvalues().iter()
+                    .map(|e| e.norm_sqr().sqrt())
+                    .filter(|&e| e > T::zero() && e < T::one() - cst(UNSTABLE_MARGIN))
+                    .fold(T::zero(), |a, b| a.max(b));
+                if lambda == T::zero() {
+                    return Err(Error::InvalidCoincidence("the plant has no stable dominant pole"));
+                }
+                let tau = -sys.sample_time / lambda.ln();
+                // 割り切れる場合に丸め誤差で1サンプル先へずれないようにする
+                (0..*n_h).map(|j| (to_f64(tau * cst((j + 1) as f64) / (sys.sample_time * cst(*n_h as f64))) * (1.0 - 1e-9)).ceil() as u32).collect()
+            },
+        };
+
+        if points.is_empty() {
+            return Err(Error::InvalidCoincidence("no coincidence point is given"));
+        }
+        if points[0] == 0 {
+            return Err(Error::InvalidCoincidence("coincidence points must be at least 1 sample ahead"));
+        }
+        if points.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(Error::InvalidCoincidence("coincidence points must be strictly increasing"));
+        }
+        if *points.last().unwrap() as usize + sys.delay > MAX_HORIZON {
+            return Err(Error::InvalidCoincidence("coincidence point is too far ahead"));
+        }
+        Ok(points)
+    }
+}
+
+/// 入力チャネル毎の設計パラメータ
+#[derive(Clone, Copy, Debug)]
+pub struct InputChannel<T = f64> {
+    pub n_b: usize,     // 基底関数の個数
+    pub basis: Basis,   // 基底関数の種類
+    pub limit: [T; 2],  // 制御入力制約 \[下限, 上限\]
+}
+
+impl<T> InputChannel<T> {
+    /// 制御入力制約だけを与える（基底関数は多項式2個）．
+    pub fn new(limit: [T; 2]) -> Self {
+        Self {n_b: 2, basis: Basis::Polynomial, limit}
+    }
+}
+
+/// 出力チャネル毎の設計パラメータ
+#[derive(Clone, Debug)]
+pub struct OutputChannel<T = f64> {
+    pub coincidence: Coincidence,         // 一致点の決め方
+    pub t_clrt: T,                        // 閉ループ応答時間
+    pub trajectory: ReferenceTrajectory,  // 参照軌道の形状
+}
+
+impl<T> OutputChannel<T> {
+    /// 閉ループ応答時間だけを与える（一致点は3個の等分，参照軌道は1次遅れ）．
+    pub fn new(t_clrt: T) -> Self {
+        Self {coincidence: Coincidence::Uniform(3), t_clrt, trajectory: ReferenceTrajectory::FirstOrder}
+    }
+}
+
+/// 固有値の絶対値が 1 - UNSTABLE_MARGIN 以上なら不安定部分として扱う
+const UNSTABLE_MARGIN: f64 = 1e-9;
+
+/// むだ時間を含めた一致点のサンプル時刻の上限（基底関数の値や予測の行列の大きさを抑える）
+const MAX_HORIZON: usize = 10000;
+
+/// 出力・状態制約を満たす入力を探すときの反復回数の上限
+const MAX_SHAPING_ITER: usize = 50;
+
+/// 出力・状態制約の違反として扱わない誤差
+const CONSTRAINT_TOLERANCE: f64 = 1e-12;
+
+/// f64の定数をスカラー型に変換する．
+fn cst<T: RealField>(v: f64) -> T {
+    nalgebra::convert(v)
+}
+
+/// スカラー型の値をf64に変換する（基底関数・参照軌道・設計レポートはf64で扱う）．
+fn to_f64<T: RealField>(v: T) -> f64 {
+    nalgebra::try_convert(v).unwrap_or(f64::NAN)
+}
+
+/// むだ時間後の一致点hにおける予測に使う行列
+///
+/// 一致点までの入力を u で保持したときの状態は x(h) = A^h x + Γ (u + d) になる．
+#[derive(Clone)]
+struct Prediction<T> {
+    phi: DMatrix<T>,      // A^h
+    gamma: DMatrix<T>,    // Γ = Σ A^(h-1-q) B
+    c_gamma: DMatrix<T>,  // C Γ
+}
+
+/// updateの途中結果を置く作業領域（毎回の確保を避けるため設計時に確保しておく）
+#[derive(Clone)]
+struct Workspace<T> {
+    r: DVector<T>,        // むだ時間後の目標値（出力数）
+    e: DVector<T>,        // 偏差・出力誤差（出力数）
+    e_u: DVector<T>,      // 不安定部分の安定化に使う出力誤差（出力数）
+    y_m: DVector<T>,      // 内部モデル出力（出力数）
+    delta_r: DVector<T>,  // 一致点での目標値の変化分（一致点数）
+    dz: DVector<T>,       // オブザーバによる修正量（状態数＋入力数）
+    x: DVector<T>,        // 内部モデル状態の更新値・一致点での自由応答（状態数）
+    v: DVector<T>,        // 入力と入力外乱の推定値の和（入力数）
+    b: Vec<T>,            // 出力・状態制約 g^T u <= b の右辺
+}
+
+/// 多入力多出力のPFC
+///
+/// 制御対象に入力むだ時間がある場合は，むだ時間を状態に含めた拡大系を内部モデルとし，
+/// 参照軌道と一致点はむだ時間後の出力を起点として扱う（スミス予測器と同等）．
+///
+/// 制御則は u = K_0 (r - y) + Nu_x x_m の形にまとまる．
+/// 将来の目標値が分かっている場合は各一致点での目標値の変化分 Δr に対する
+/// 項 Nu_r Δr が加わる．
+/// オフセットフリーモードでは入力外乱の推定値 d_m を差し引いた
+/// u = K_0 (r - y) + Nu_x x_m - d_m となる．
+///
+/// スカラー型 T は設計から実行まで共通で，f32 にすれば組み込み向けの精度で動かせる．
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone)]
+pub struct PFC<T = f64> {
+    a_m: DMatrix<T>,  // 内部モデルのシステム行列
+    b_m: DMatrix<T>,  // 内部モデルの入力行列
+    c_m: DMatrix<T>,  // 内部モデルの出力行列
+    x_m: DVector<T>,  // 内部モデルの状態変数
+    k_0: DMatrix<T>,  // 偏差に対するゲイン（入力数×出力数）
+    nu_x: DMatrix<T>, // 内部モデル状態に対するゲイン（入力数×状態数）
+    nu_r: DMatrix<T>, // 一致点での目標値の変化分に対するゲイン（入力数×一致点数）
+    report: DesignReport,  // 設計結果のレポート
+    horizons: Vec<(usize, u32)>,  // 各一致点の (出力チャネル, むだ時間を除いたサンプル時刻)
+    delay: u32,         // 入力むだ時間[サンプル]
+    sample_time: T,     // 離散化周期
+    d_m: DVector<T>,  // 入力外乱の推定値
+    observer: Option<DMatrix<T>>,  // 内部モデル状態と入力外乱を推定するオブザーバゲイン
+    stabilizer: Option<DMatrix<T>>,  // 内部モデルの不安定部分を安定化するゲイン
+    u_prev: DVector<T>,  // 前回の制御入力
+    active: Vec<ActiveConstraint>,  // 前回の更新で掛かった制約
+    feasible: bool,  // 前回の更新で出力・状態制約を満たせたか
+    predictions: Vec<Prediction<T>>,  // むだ時間後の各一致点における予測
+    work: Workspace<T>,  // updateの作業領域
+    limit: Vec<[T; 2]>,  // 入力チャネル毎の制御入力制約 \[下限, 上限\]
+    rate_limit: Option<Vec<[T; 2]>>,  // 1サンプルあたりの入力変化量の制約 \[下限, 上限\]
+    output_limit: Option<Vec<[T; 2]>>,  // 出力チャネル毎の出力制約 \[下限, 上限\]
+    state_limit: Option<Vec<(usize, [T; 2])>>,  // (状態変数の番号, \[下限, 上限\])
+}
+
+impl<T: RealField + Copy> PFC<T> {
+    /// * sys: 離散時間状態空間モデル
+    /// * inputs: 入力チャネル毎の基底関数と制御入力制約
+    /// * outputs: 出力チャネル毎の一致点・閉ループ応答時間・参照軌道
+    pub fn new(sys: &StateSpace<T>, inputs: &[InputChannel<T>], outputs: &[OutputChannel<T>]) -> Result<Self, Error> {
+        if sys.sample_time == T::zero() {
+            return Err(Error::NotDiscrete);
+        }
+        if !(sys.sample_time > T::zero() && sys.sample_time.is_finite()) {
+            return Err(Error::InvalidSampleTime);
+        }
+        if inputs.len() != sys.b.ncols() {
+            return Err(Error::DimensionMismatch("number of input channel parameters does not match the plant"));
+        }
+        check_bounds(inputs.iter().map(|input| &input.limit))?;
+        if outputs.len() != sys.c.nrows() {
+            return Err(Error::DimensionMismatch("number of output channel parameters does not match the plant"));
+        }
+
+        // 一致点（むだ時間を除いたサンプル時刻）
+        let h_points = outputs.iter()
+            .map(|output| output.coincidence.resolve(sys, output.t_clrt))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let delay = sys.delay as u32;
+        let sys = &sys.delay_augmented();
         let n = sys.a.nrows();
-        let (k_0, nu_x) = offline_designer(&sys, n_b, n_h, t_clrt);
-        Self {
+        let l = sys.b.ncols();
+        let design = offline_designer(sys, inputs, outputs, &h_points, delay)?;
+        let horizons: Vec<(usize, u32)> = h_points.iter().enumerate()
+            .flat_map(|(i, points)| points.iter().map(move |&h| (i, h)))
+            .collect();
+
+        // 公称モデルでの閉ループ極
+        let a_cl = &sys.a + &sys.b * (&design.nu_x - &design.k_0 * &sys.c);
+        let report = DesignReport {
+            k_0: design.k_0.map(to_f64),
+            nu_x: design.nu_x.map(to_f64),
+            nu_r: design.nu_r.map(to_f64),
+            horizons: horizons.clone(),
+            delay,
+            trajectory_remaining: design.trajectory_remaining,
+            basis_responses: design.basis_responses.map(to_f64),
+            condition_number: to_f64(design.condition_number),
+            closed_loop_poles: a_cl.complex_eigenvalues().iter().map(|e| Complex::new(to_f64(e.re), to_f64(e.im))).collect(),
+        };
+
+        // 制約の判定に使う一致点（全出力チャネル分をまとめる）
+        let mut h_all: Vec<u32> = h_points.iter().flatten().map(|&h| h + delay).collect();
+        h_all.sort_unstable();
+        h_all.dedup();
+        let predictions: Vec<Prediction<T>> = h_all.iter().map(|&h| {
+            let mut gamma = DMatrix::<T>::zeros(n, l);
+            for q in 0..h {
+                gamma += sys.a.pow(h - 1 - q) * &sys.b;
+            }
+            Prediction {phi: sys.a.pow(h), c_gamma: &sys.c * &gamma, gamma}
+        }).collect();
+        let p = sys.c.nrows();
+        let work = Workspace {
+            r: DVector::<T>::zeros(p),
+            e: DVector::<T>::zeros(p),
+            e_u: DVector::<T>::zeros(p),
+            y_m: DVector::<T>::zeros(p),
+            delta_r: DVector::<T>::zeros(horizons.len()),
+            dz: DVector::<T>::zeros(n + l),
+            x: DVector::<T>::zeros(n),
+            v: DVector::<T>::zeros(l),
+            b: Vec::new(),  // 制約を設定したときに確保する
+        };
+
+        Ok(Self {
             a_m: sys.a.clone(),
             b_m: sys.b.clone(),
-            x_m: DVector::<f64>::zeros(n),
-            k_0: k_0,
-            nu_x_transpose: nu_x.transpose(),
-            limit: limit
+            c_m: sys.c.clone(),
+            x_m: DVector::<T>::zeros(n),
+            k_0: design.k_0,
+            nu_x: design.nu_x,
+            nu_r: design.nu_r,
+            report,
+            horizons,
+            delay,
+            sample_time: sys.sample_time,
+            d_m: DVector::<T>::zeros(l),
+            observer: None,
+            stabilizer: stabilizing_gain(sys)?,
+            u_prev: DVector::<T>::zeros(l),
+            active: vec![ActiveConstraint::None; l],
+            feasible: true,
+            predictions,
+            work,
+            limit: inputs.iter().map(|input| input.limit).collect(),
+            rate_limit: None,
+            output_limit: None,
+            state_limit: None,
+        })
+    }
+
+    /// 設計結果のレポートを返す．
+    pub fn report(&self) -> &DesignReport {
+        &self.report
+    }
+
+    /// 組み込み向けの静的サイズのPFCに変換する．
+    ///
+    /// ゲイン・内部モデル・入力制約・変化率制約を引き継ぎ，内部状態は初期状態から始める．
+    /// 静的サイズのPFCは出力・状態制約を扱わないので，設定されていればエラーを返す．
+    ///
+    /// N: 内部モデルの状態数（むだ時間分を含む），L: 入力数，P: 出力数，H: 一致点の総数
+    pub fn to_static<const N: usize, const L: usize, const P: usize, const H: usize>(&self) -> Result<StaticPFC<T, N, L, P, H>, Error> {
+        self.check_runtime_support()?;
+        let n = self.a_m.nrows();
+        if n != N || self.b_m.ncols() != L || self.c_m.nrows() != P || self.horizons.len() != H {
+            return Err(Error::DimensionMismatch("sizes of the static PFC do not match the design"));
+        }
+
+        // DMatrixもSMatrixも列優先なので要素をそのまま並べ直せばよい
+        let gains = StaticGains {
+            a_m: SMatrix::from_iterator(self.a_m.iter().cloned()),
+            b_m: SMatrix::from_iterator(self.b_m.iter().cloned()),
+            c_m: SMatrix::from_iterator(self.c_m.iter().cloned()),
+            k_0: SMatrix::from_iterator(self.k_0.iter().cloned()),
+            nu_x: SMatrix::from_iterator(self.nu_x.iter().cloned()),
+            nu_r: SMatrix::from_iterator(self.nu_r.iter().cloned()),
+            horizons: self.horizons.clone().try_into().unwrap(),
+            delay: self.delay,
+            observer: self.observer.as_ref().map(|observer| (
+                SMatrix::from_iterator(observer.rows(0, N).iter().cloned()),
+                SMatrix::from_iterator(observer.rows(N, L).iter().cloned()),
+            )),
+            stabilizer: self.stabilizer.as_ref().map(|stabilizer| SMatrix::from_iterator(stabilizer.iter().cloned())),
+        };
+        let limit = self.limit.clone().try_into()
+            .map_err(|_| Error::DimensionMismatch("number of input limits does not match the plant"))?;
+        let mut pfc = StaticPFC::new(gains, limit);
+        if let Some(rate_limit) = &self.rate_limit {
+            pfc.rate_limit = Some(rate_limit.clone().try_into()
+                .map_err(|_| Error::DimensionMismatch("number of rate limits does not match the plant"))?);
+        }
+        Ok(pfc)
+    }
+
+    /// 組み込み向けの実行部（pfc-runtime）に渡すゲインをバイト列に書き出す．
+    ///
+    /// 形式は pfc_runtime::format を参照．ファームウェア側では StaticPFC::from_bytes で読み込む．
+    /// to_static と同じく，出力・状態制約が設定されていればエラーを返す．
+    pub fn serialize_gains(&self) -> Result<Vec<u8>, Error> {
+        self.check_runtime_support()?;
+        let n = self.a_m.nrows();
+        let l = self.b_m.ncols();
+
+        let mut flags = 0;
+        if self.observer.is_some() {
+            flags |= format::FLAG_OBSERVER;
+        }
+        if self.stabilizer.is_some() {
+            flags |= format::FLAG_STABILIZER;
+        }
+        if self.rate_limit.is_some() {
+            flags |= format::FLAG_RATE_LIMIT;
+        }
+        let header = format::Header {
+            flags,
+            n: n as u32,
+            l: l as u32,
+            p: self.c_m.nrows() as u32,
+            h: self.horizons.len() as u32,
+            delay: self.delay,
+        };
+
+        let mut bytes = Vec::with_capacity(header.total_len());
+        bytes.extend_from_slice(&header.encode());
+        for &(i, h) in self.horizons.iter() {
+            bytes.extend_from_slice(&(i as u32).to_le_bytes());
+            bytes.extend_from_slice(&h.to_le_bytes());
+        }
+
+        for v in self.gain_values() {
+            bytes.extend_from_slice(&to_f64(v).to_le_bytes());
+        }
+
+        if bytes.len() != header.total_len() {
+            return Err(Error::DimensionMismatch("number of limits does not match the plant"));
+        }
+        Ok(bytes)
+    }
+
+    /// serialize_gainsで書き出す実数値（形式で決めた順に列優先で並べる）
+    fn gain_values(&self) -> Vec<T> {
+        let n = self.a_m.nrows();
+        let l = self.b_m.ncols();
+        let mut values: Vec<T> = Vec::new();
+        for m in [&self.a_m, &self.b_m, &self.c_m, &self.k_0, &self.nu_x, &self.nu_r] {
+            values.extend(m.iter());
+        }
+        if let Some(observer) = &self.observer {
+            values.extend(observer.rows(0, n).iter());
+            values.extend(observer.rows(n, l).iter());
+        }
+        if let Some(stabilizer) = &self.stabilizer {
+            values.extend(stabilizer.iter());
+        }
+        for range in self.limit.iter().chain(self.rate_limit.iter().flatten()) {
+            values.extend(range);
+        }
+        values
+    }
+
+    /// 固定小数点数（pfc_runtime::fixed::Fixed<FRAC>）の実行部で使うときの量子化を評価する．
+    ///
+    /// StaticPFC::from_bytes と同じ丸めで K_0・Nu_x・Nu_r・A_m・B_m の量子化誤差を求め，
+    /// 量子化した行列で内部モデル（安定化・オフセットフリーモードの修正を含む）が安定なままか調べる．
+    /// serialize_gains で書き出す値（入力制約・変化率制約を含む）に表せる範囲を超えるものがあればエラーを返す．
+    pub fn quantize<const FRAC: u32>(&self) -> Result<QuantizationReport, Error> {
+        // 書き出す値が1つでも範囲外なら，実行部で読み込めない
+        if self.gain_values().into_iter().any(|v| Fixed::<FRAC>::from_f64(to_f64(v)).is_none()) {
+            return Err(Error::InvalidParameter("gain or limit exceeds the range of the fixed-point format"));
+        }
+
+        // (量子化前, 量子化後)．範囲は確認済み
+        let quantize = |m: &DMatrix<T>| -> (DMatrix<f64>, DMatrix<f64>) {
+            let m = m.map(to_f64);
+            let q = m.map(|v| Fixed::<FRAC>::from_f64(v).unwrap().to_f64());
+            (m, q)
+        };
+        let max_error = |(m, q): &(DMatrix<f64>, DMatrix<f64>)| (m - q).amax();
+        let a_m = quantize(&self.a_m);
+        let b_m = quantize(&self.b_m);
+        let c_m = quantize(&self.c_m);
+        let k_0 = quantize(&self.k_0);
+        let nu_x = quantize(&self.nu_x);
+        let nu_r = quantize(&self.nu_r);
+        let observer = self.observer.as_ref().map(quantize);
+        let stabilizer = self.stabilizer.as_ref().map(quantize);
+
+        let radius = |a: DMatrix<f64>| a.complex_eigenvalues().iter().map(|e| e.norm_sqr().sqrt()).fold(0.0, f64::max);
+        let spectral_radius = radius(internal_model_dynamics(
+            &a_m.0, &b_m.0, &c_m.0, observer.as_ref().map(|m| &m.0), stabilizer.as_ref().map(|m| &m.0)));
+        let quantized_spectral_radius = radius(internal_model_dynamics(
+            &a_m.1, &b_m.1, &c_m.1, observer.as_ref().map(|m| &m.1), stabilizer.as_ref().map(|m| &m.1)));
+        Ok(QuantizationReport {
+            frac_bits: FRAC,
+            k_0: max_error(&k_0),
+            nu_x: max_error(&nu_x),
+            nu_r: max_error(&nu_r),
+            a_m: max_error(&a_m),
+            b_m: max_error(&b_m),
+            spectral_radius,
+            quantized_spectral_radius,
+        })
+    }
+
+    /// 組み込み向けの実行部で扱える設定か確認する．
+    fn check_runtime_support(&self) -> Result<(), Error> {
+        if self.output_limit.is_some() || self.state_limit.is_some() {
+            return Err(Error::InvalidParameter("the static PFC does not support output or state constraints"));
+        }
+        Ok(())
+    }
+
+    /// 制約なし・目標値一定のときのPFCと等価な線形コントローラを返す．
+    ///
+    /// 入力は \[r; y\]（目標値と制御対象出力），出力は u の離散時間状態空間モデルで，
+    /// 状態は内部モデルの状態（オフセットフリーモードでは入力外乱の推定値も含む）．
+    /// 内部モデルの安定化とオフセットフリーモードのオブザーバも含める．
+    pub fn linear_controller(&self) -> StateSpace<T> {
+        let n = self.a_m.nrows();
+        let l = self.b_m.ncols();
+        let p = self.c_m.nrows();
+
+        // 内部モデル（オフセットフリーモードでは入力外乱を加えた拡大系）
+        let n_z = if self.observer.is_some() {n + l} else {n};
+        let mut a_z = DMatrix::<T>::identity(n_z, n_z);
+        a_z.slice_mut((0, 0), (n, n)).copy_from(&self.a_m);
+        let mut b_z = DMatrix::<T>::zeros(n_z, l);
+        b_z.slice_mut((0, 0), (n, l)).copy_from(&self.b_m);
+        let mut c_z = DMatrix::<T>::zeros(p, n_z);
+        c_z.slice_mut((0, 0), (p, n)).copy_from(&self.c_m);
+        let mut g = DMatrix::<T>::zeros(l, n_z);
+        g.slice_mut((0, 0), (l, n)).copy_from(&self.nu_x);
+        if n_z > n {
+            a_z.slice_mut((0, n), (n, l)).copy_from(&self.b_m);
+            g.slice_mut((0, n), (l, l)).copy_from(&-DMatrix::<T>::identity(l, l));
+        }
+
+        // 観測値による修正: 更新前に z += L_f e，更新後に z += L_p e（e = y - C z）
+        let l_f = match &self.observer {
+            Some(observer) => observer.clone(),
+            None => DMatrix::<T>::zeros(n_z, p),
+        };
+        let l_p = match (&self.observer, &self.stabilizer) {
+            (None, Some(stabilizer)) => stabilizer.clone(),
+            _ => DMatrix::<T>::zeros(n_z, p),
+        };
+
+        // u = G M z + K_0 r + (G L_f - K_0) y,  M = I - L_f C
+        let m = DMatrix::<T>::identity(n_z, n_z) - &l_f * &c_z;
+        let c_c = &g * &m;
+        let d_y = &g * &l_f - &self.k_0;
+        let a_c = (&a_z + &b_z * &g) * &m - &l_p * &c_z;
+        let b_y = &a_z * &l_f + &b_z * &d_y + &l_p;
+        let b_r = &b_z * &self.k_0;
+
+        let mut b_c = DMatrix::<T>::zeros(n_z, 2 * p);
+        b_c.slice_mut((0, 0), (n_z, p)).copy_from(&b_r);
+        b_c.slice_mut((0, p), (n_z, p)).copy_from(&b_y);
+        let mut d_c = DMatrix::<T>::zeros(l, 2 * p);
+        d_c.slice_mut((0, 0), (l, p)).copy_from(&self.k_0);
+        d_c.slice_mut((0, p), (l, p)).copy_from(&d_y);
+
+        StateSpace::new(a_c, b_c, c_c, d_c, self.sample_time).unwrap()
+    }
+
+    /// オフセットフリーモードを有効にする．
+    ///
+    /// 内部モデルに一定値の入力外乱 d を加えた拡大系
+    ///
+    /// x(k+1) = A x(k) + B (u(k) + d(k)),  d(k+1) = d(k)
+    ///
+    /// に対する定常カルマンフィルタで内部モデルの状態と外乱を推定し，
+    /// 推定した外乱を打ち消すように入力を補正する．
+    /// 入力側のステップ外乱やモデル化誤差があっても定常偏差が残らない．
+    ///
+    /// --- Arguments ---
+    /// * q_x: 状態のプロセスノイズ分散
+    /// * q_d: 入力外乱のプロセスノイズ分散（大きいほど外乱推定が速い）
+    /// * r_y: 観測ノイズ分散
+    pub fn enable_offset_free(&mut self, q_x: T, q_d: T, r_y: T) -> Result<(), Error> {
+        if !(q_x >= T::zero() && q_d > T::zero() && r_y > T::zero()) {
+            return Err(Error::InvalidParameter("noise variances must be positive"));
+        }
+
+        let n = self.a_m.nrows();
+        let l = self.b_m.ncols();
+        let p = self.c_m.nrows();
+
+        // 拡大系
+        let mut a = DMatrix::<T>::identity(n + l, n + l);
+        a.slice_mut((0, 0), (n, n)).copy_from(&self.a_m);
+        a.slice_mut((0, n), (n, l)).copy_from(&self.b_m);
+        let mut c = DMatrix::<T>::zeros(p, n + l);
+        c.slice_mut((0, 0), (p, n)).copy_from(&self.c_m);
+        let mut q = DMatrix::<T>::zeros(n + l, n + l);
+        for i in 0..(n + l) {
+            q[(i, i)] = if i < n {q_x} else {q_d};
+        }
+        let r = DMatrix::<T>::identity(p, p) * r_y;
+
+        check_detectable(&a, &c)?;
+        let p_mat = solve_riccati(&a, &c, &q, &r)?;
+        let s_inv = (&c * &p_mat * c.transpose() + &r).try_inverse()
+            .ok_or(Error::SingularMatrix("innovation covariance of the disturbance observer"))?;
+        self.observer = Some(&p_mat * c.transpose() * s_inv);
+        Ok(())
+    }
+
+    /// 内部モデルを安定部分と安定化した不安定部分に分解しているかを返す．
+    pub fn is_decomposed(&self) -> bool {
+        self.stabilizer.is_some()
+    }
+
+    /// 入力外乱の推定値を返す（オフセットフリーモード以外では常に0）．
+    pub fn disturbance(&self) -> &DVector<T> {
+        &self.d_m
+    }
+
+    /// 直前のupdateで各入力チャネルに掛かった制約を返す．
+    pub fn active_constraint(&self) -> &[ActiveConstraint] {
+        &self.active
+    }
+
+    /// 直前のupdateで出力・状態制約を全て満たす入力が見つかったかを返す
+    /// （変化率・入力制約を適用した後の入力で判定する）．
+    pub fn is_feasible(&self) -> bool {
+        self.feasible
+    }
+
+    /// 入力チャネル毎の制御入力制約を返す．
+    pub fn limit(&self) -> &[[T; 2]] {
+        &self.limit
+    }
+
+    /// 1サンプルあたりの入力変化量の制約を返す．
+    pub fn rate_limit(&self) -> Option<&[[T; 2]]> {
+        self.rate_limit.as_deref()
+    }
+
+    /// 出力チャネル毎の出力制約を返す．
+    pub fn output_limit(&self) -> Option<&[[T; 2]]> {
+        self.output_limit.as_deref()
+    }
+
+    /// 状態制約を返す．
+    pub fn state_limit(&self) -> Option<&[(usize, [T; 2])]> {
+        self.state_limit.as_deref()
+    }
+
+    /// 入力チャネル毎の制御入力制約を設定する．
+    ///
+    /// --- Arguments ---
+    /// * limit: 入力チャネル毎の \[下限, 上限\]
+    pub fn set_limit(&mut self, limit: &[[T; 2]]) -> Result<(), Error> {
+        if limit.len() != self.b_m.ncols() {
+            return Err(Error::DimensionMismatch("number of input limits does not match the plant"));
         }
+        check_bounds(limit.iter())?;
+        self.limit = limit.to_vec();
+        Ok(())
+    }
+
+    /// 1サンプルあたりの入力変化量の制約を設定する（None で解除）．
+    ///
+    /// --- Arguments ---
+    /// * rate_limit: 入力チャネル毎の \[下限, 上限\]
+    pub fn set_rate_limit(&mut self, rate_limit: Option<&[[T; 2]]>) -> Result<(), Error> {
+        if let Some(rate_limit) = rate_limit {
+            if rate_limit.len() != self.b_m.ncols() {
+                return Err(Error::DimensionMismatch("number of rate limits does not match the plant"));
+            }
+            check_bounds(rate_limit.iter())?;
+        }
+        self.rate_limit = rate_limit.map(|r| r.to_vec());
+        Ok(())
+    }
+
+    /// 一致点における出力の予測値に対する制約を設定する（None で解除）．
+    ///
+    /// --- Arguments ---
+    /// * output_limit: 出力チャネル毎の \[下限, 上限\]
+    pub fn set_output_limit(&mut self, output_limit: Option<&[[T; 2]]>) -> Result<(), Error> {
+        if let Some(output_limit) = output_limit {
+            if output_limit.len() != self.c_m.nrows() {
+                return Err(Error::DimensionMismatch("number of output limits does not match the plant"));
+            }
+            check_bounds(output_limit.iter())?;
+        }
+        self.output_limit = output_limit.map(|o| o.to_vec());
+        self.reserve_constraints();
+        Ok(())
+    }
+
+    /// 一致点における内部モデル状態の予測値に対する制約を設定する（None で解除）．
+    ///
+    /// 状態変数の番号はむだ時間分を含めた内部モデルの状態の番号で，
+    /// 制御対象の状態はむだ時間があっても先頭から同じ番号で並ぶ．
+    ///
+    /// --- Arguments ---
+    /// * state_limit: (状態変数の番号, \[下限, 上限\]) の並び
+    pub fn set_state_limit(&mut self, state_limit: Option<&[(usize, [T; 2])]>) -> Result<(), Error> {
+        if let Some(state_limit) = state_limit {
+            if state_limit.iter().any(|&(s, _)| s >= self.a_m.nrows()) {
+                return Err(Error::DimensionMismatch("state index of the state limit is out of range"));
+            }
+            check_bounds(state_limit.iter().map(|(_, limit)| limit))?;
+        }
+        self.state_limit = state_limit.map(|s| s.to_vec());
+        self.reserve_constraints();
+        Ok(())
+    }
+
+    /// 出力・状態制約の右辺を置く作業領域を確保しておく（updateで確保しないように）．
+    fn reserve_constraints(&mut self) {
+        let n_limit = self.output_limit.as_ref().map_or(0, |o| o.len()) + self.state_limit.as_ref().map_or(0, |s| s.len());
+        self.work.b.clear();
+        self.work.b.reserve(2 * self.predictions.len() * n_limit);
+    }
+
+    /// 内部モデルの状態・入力外乱の推定値・前回の制御入力を初期状態に戻す．
+    ///
+    /// ゲインと制約の設定はそのまま残る．
+    pub fn reset(&mut self) {
+        self.x_m.fill(T::zero());
+        self.d_m.fill(T::zero());
+        self.u_prev.fill(T::zero());
+        self.active.fill(ActiveConstraint::None);
+        self.feasible = true;
     }
 
     /// 制御入力を計算して内部モデルを更新する．
-    /// 
+    ///
     /// --- Arguments ---
     /// * r: 目標値
     /// * y: 制御対象出力
-    /// 
+    ///
     /// ---- Return -----
-    /// * u: 制御入力
-    pub fn update(&mut self, r: f64, y: f64) -> f64 {
-        let mut u = self.k_0 * (r - y) + (self.nu_x_transpose.clone() * self.x_m.clone())[0];
+    /// * u: 制約を考慮した制御入力
+    pub fn update(&mut self, r: &DVector<T>, y: &DVector<T>) -> DVector<T> {
+        self.update_with_preview(&Setpoint::Constant(r), y)
+    }
 
-        // 入力制約
-        if u < self.limit[0] {
-            u = self.limit[0]
-        } else if u > self.limit[1] {
-            u = self.limit[1]
+    /// 将来の目標値を使って制御入力を計算し，内部モデルを更新する．
+    ///
+    /// 各一致点では既知の目標値に向かう参照軌道を使うので，
+    /// ランプ状の目標値でも基底関数が足りていれば追従遅れが残らない．
+    ///
+    /// --- Arguments ---
+    /// * setpoint: 現在時刻以降の目標値
+    /// * y: 制御対象出力
+    ///
+    /// ---- Return -----
+    /// * u: 制約を考慮した制御入力
+    pub fn update_with_preview(&mut self, setpoint: &Setpoint<T>, y: &DVector<T>) -> DVector<T> {
+        let mut u = DVector::<T>::zeros(self.b_m.ncols());
+        self.update_into(setpoint, y, &mut u);
+        u
+    }
+
+    /// 制御入力を渡されたベクトルに書き込み，内部モデルを更新する．
+    ///
+    /// 途中結果は設計時・制約の設定時に確保した作業領域に置くので，動的確保をしない．
+    ///
+    /// --- Arguments ---
+    /// * setpoint: 現在時刻以降の目標値（空の系列なら前回の入力を保持する）
+    /// * y: 制御対象出力
+    /// * u: 制約を考慮した制御入力の書き込み先（入力数）
+    pub fn update_into(&mut self, setpoint: &Setpoint<T>, y: &DVector<T>, u: &mut DVector<T>) {
+        let work = &mut self.work;
+
+        // 不安定部分の安定化に使う出力誤差
+        let stabilize = self.observer.is_none() && self.stabilizer.is_some();
+        if stabilize {
+            work.e_u.copy_from(y);
+            work.e_u.gemv(-T::one(), &self.c_m, &self.x_m, T::one());
         }
 
-        // 内部モデル更新
-        self.x_m = self.a_m.clone() * self.x_m.clone() + self.b_m.clone() * u;
-        u
+        // オフセットフリーモードでは観測値で内部モデル状態と外乱の推定値を修正する
+        // （このオブザーバは不安定部分も安定化するので分解は使わない）
+        if let Some(observer) = &self.observer {
+            let n = self.x_m.len();
+            work.e.copy_from(y);
+            work.e.gemv(-T::one(), &self.c_m, &self.x_m, T::one());
+            work.dz.gemv(T::one(), observer, &work.e, T::zero());
+            self.x_m += work.dz.rows(0, n);
+            self.d_m += work.dz.rows(n, self.d_m.len());
+        }
+
+        if setpoint.is_empty() {
+            // 目標値が与えられなければ前回の入力を保持する（内部モデルの更新は続ける）
+            u.copy_from(&self.u_prev);
+            self.active.fill(ActiveConstraint::None);
+            self.feasible = true;
+        } else {
+            self.control_input(setpoint, y, u);
+        }
+
+        // 実際に印加した入力で内部モデル更新
+        let work = &mut self.work;
+        work.v.copy_from(u);
+        work.v += &self.d_m;
+        work.x.gemv(T::one(), &self.a_m, &self.x_m, T::zero());
+        work.x.gemv(T::one(), &self.b_m, &work.v, T::one());
+        if let (true, Some(stabilizer)) = (stabilize, &self.stabilizer) {
+            work.x.gemv(T::one(), stabilizer, &work.e_u, T::one());
+        }
+        std::mem::swap(&mut self.x_m, &mut work.x);
+        self.u_prev.copy_from(u);
+    }
+
+    /// 目標値と制御対象出力から，制約を考慮した制御入力を計算する．
+    ///
+    /// --- Arguments ---
+    /// * setpoint: 現在時刻以降の目標値（空でないこと）
+    /// * y: 制御対象出力
+    /// * u: 制約を考慮した制御入力の書き込み先（入力数）
+    fn control_input(&mut self, setpoint: &Setpoint<T>, y: &DVector<T>, u: &mut DVector<T>) {
+        let work = &mut self.work;
+
+        // むだ時間後の目標値を起点とし，各一致点までの目標値の変化分を求める
+        for i in 0..work.r.len() {
+            work.r[i] = setpoint.at(self.delay, i);
+            work.e[i] = work.r[i] - y[i];
+        }
+        work.delta_r.fill(T::zero());
+        if !matches!(setpoint, Setpoint::Constant(_)) {
+            for (row, &(i, h)) in self.horizons.iter().enumerate() {
+                work.delta_r[row] = setpoint.at(self.delay + h, i) - work.r[i];
+            }
+        }
+
+        // u = K_0 (r - y) + Nu_x x_m + Nu_r Δr - d_m
+        u.gemv(T::one(), &self.k_0, &work.e, T::zero());
+        u.gemv(T::one(), &self.nu_x, &self.x_m, T::one());
+        u.gemv(T::one(), &self.nu_r, &work.delta_r, T::one());
+        *u -= &self.d_m;
+
+        // 出力・状態制約
+        self.active.fill(ActiveConstraint::None);
+        self.feasible = self.shape_input(u, y);
+
+        for j in 0..u.len() {
+            // 変化率制約
+            if let Some(rate_limit) = &self.rate_limit {
+                let lower = self.u_prev[j] + rate_limit[j][0];
+                let upper = self.u_prev[j] + rate_limit[j][1];
+                if u[j] < lower {
+                    u[j] = lower;
+                    self.active[j] = ActiveConstraint::RateLower;
+                } else if u[j] > upper {
+                    u[j] = upper;
+                    self.active[j] = ActiveConstraint::RateUpper;
+                }
+            }
+
+            // 入力制約（変化率制約と両立しない場合はこちらを優先する）
+            if u[j] < self.limit[j][0] {
+                u[j] = self.limit[j][0];
+                self.active[j] = ActiveConstraint::Lower;
+            } else if u[j] > self.limit[j][1] {
+                u[j] = self.limit[j][1];
+                self.active[j] = ActiveConstraint::Upper;
+            }
+        }
+
+        // 変化率・入力制約で動かした入力で出力・状態制約を満たせるか確かめ直す
+        if self.feasible && (self.output_limit.is_some() || self.state_limit.is_some()) {
+            self.feasible = self.satisfies_constraints(u);
+        }
+    }
+
+    /// 一致点における予測値が出力・状態制約を満たすように入力を修正する．
+    ///
+    /// 一致点までの入力を現在値で保持したときの予測値は入力について線形なので，
+    /// 各制約は入力空間の半空間になる．違反している半空間への射影を繰り返して
+    /// 全ての半空間の共通部分に入る入力を探す．共通部分が空の場合は
+    /// 反復上限で打ち切った入力（違反量を均した妥協解）をそのまま使う．
+    ///
+    /// --- Arguments ---
+    /// * u: 制約なしの制御入力（修正後の値で上書きする）
+    /// * y: 制御対象出力
+    ///
+    /// ---- Return -----
+    /// * 全ての制約を満たせたか
+    fn shape_input(&mut self, u: &mut DVector<T>, y: &DVector<T>) -> bool {
+        if self.output_limit.is_none() && self.state_limit.is_none() {
+            return true;
+        }
+
+        // g^T u <= b の形で制約を並べる．g は予測の行列の行（下限側は符号を反転）なので
+        // 右辺 b だけを求めておく
+        let work = &mut self.work;
+        work.b.clear();
+        work.y_m.gemv(T::one(), &self.c_m, &self.x_m, T::zero());
+        for prediction in self.predictions.iter() {
+            work.x.gemv(T::one(), &prediction.phi, &self.x_m, T::zero());
+            work.x.gemv(T::one(), &prediction.gamma, &self.d_m, T::one());
+            if let Some(output_limit) = &self.output_limit {
+                // 内部モデルとプラントの出力差は一致点まで一定とみなす
+                for (i, limit) in output_limit.iter().enumerate() {
+                    let y_free = self.c_m.row(i).tr_dot(&work.x) + y[i] - work.y_m[i];
+                    work.b.push(y_free - limit[0]);
+                    work.b.push(limit[1] - y_free);
+                }
+            }
+            if let Some(state_limit) = &self.state_limit {
+                for &(s, limit) in state_limit.iter() {
+                    work.b.push(work.x[s] - limit[0]);
+                    work.b.push(limit[1] - work.x[s]);
+                }
+            }
+        }
+
+        for _ in 0..MAX_SHAPING_ITER {
+            let mut satisfied = true;
+            let mut b = work.b.iter();
+            for prediction in self.predictions.iter() {
+                if let Some(output_limit) = &self.output_limit {
+                    for i in 0..output_limit.len() {
+                        let g = prediction.c_gamma.row(i);
+                        satisfied &= project(u, g, -T::one(), *b.next().unwrap(), &mut self.active, ActiveConstraint::Output);
+                        satisfied &= project(u, g, T::one(), *b.next().unwrap(), &mut self.active, ActiveConstraint::Output);
+                    }
+                }
+                if let Some(state_limit) = &self.state_limit {
+                    for &(s, _) in state_limit.iter() {
+                        let g = prediction.gamma.row(s);
+                        satisfied &= project(u, g, -T::one(), *b.next().unwrap(), &mut self.active, ActiveConstraint::State);
+                        satisfied &= project(u, g, T::one(), *b.next().unwrap(), &mut self.active, ActiveConstraint::State);
+                    }
+                }
+            }
+            if satisfied {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// 入力が一致点における出力・状態制約を全て満たすか確認する．
+    ///
+    /// 制約の右辺は直前の shape_input で求めたものを使う．
+    fn satisfies_constraints(&self, u: &DVector<T>) -> bool {
+        let tol = cst::<T>(CONSTRAINT_TOLERANCE);
+        let mut b = self.work.b.iter();
+        let mut within = |g_u: T| {
+            let (b_lower, b_upper) = (*b.next().unwrap(), *b.next().unwrap());
+            -g_u - b_lower <= tol && g_u - b_upper <= tol
+        };
+        for prediction in self.predictions.iter() {
+            if let Some(output_limit) = &self.output_limit {
+                for i in 0..output_limit.len() {
+                    if !within(prediction.c_gamma.row(i).tr_dot(u)) {
+                        return false;
+                    }
+                }
+            }
+            if let Some(state_limit) = &self.state_limit {
+                for &(s, _) in state_limit.iter() {
+                    if !within(prediction.gamma.row(s).tr_dot(u)) {
+                        return false;
+                    }
+                }
+            }
+        }
+        true
+    }
+}
+
+impl<T: RealField + Copy> Controller<T> for PFC<T> {
+    fn reset(&mut self) {
+        PFC::reset(self);
+    }
+
+    fn update(&mut self, setpoint: &Setpoint<T>, y: &DVector<T>) -> DVector<T> {
+        self.update_with_preview(setpoint, y)
+    }
+
+    fn input_limits(&self) -> &[[T; 2]] {
+        &self.limit
+    }
+
+    /// 内部モデルの状態（オフセットフリーモードでは入力外乱の推定値を続けて並べる）．
+    /// linear_controller の状態と同じ並び．
+    fn state(&self) -> DVector<T> {
+        if self.observer.is_some() {
+            DVector::from_iterator(self.x_m.len() + self.d_m.len(), self.x_m.iter().chain(self.d_m.iter()).cloned())
+        } else {
+            self.x_m.clone()
+        }
+    }
+}
+
+/// 制約の \[下限, 上限\] がそれぞれ数値で，下限が上限以下か確認する．
+fn check_bounds<'a, T: RealField + Copy>(bounds: impl Iterator<Item = &'a [T; 2]>) -> Result<(), Error> {
+    for bound in bounds {
+        // NaNを含むと比較できない（None）のでここで弾かれる
+        if !matches!(bound[0].partial_cmp(&bound[1]), Some(Ordering::Less | Ordering::Equal)) {
+            return Err(Error::InvalidParameter("lower bound of a constraint must not exceed the upper bound"));
+        }
+    }
+    Ok(())
+}
+
+/// 半空間 sign g^T u <= b に違反していれば u を境界へ射影する．
+///
+///  ------- Arguments -------
+///  * u: 制御入力（射影後の値で上書きする）
+///  * g: 半空間の法線（予測の行列の行）
+///  * sign: 法線の符号（下限側の制約は -1）
+///  * b: 右辺
+///  * active: 射影で動かした入力チャネルに制約の種類を書き込む
+///  * kind: 制約の種類
+///
+///  -------- Return ---------
+///  * 違反していなかったか
+fn project<T: RealField + Copy>(u: &mut DVector<T>, g: MatrixSlice1xX<T, U1, Dynamic>, sign: T, b: T, active: &mut [ActiveConstraint], kind: ActiveConstraint) -> bool {
+    let violation = sign * g.tr_dot(u) - b;
+    let g_norm2 = g.norm_squared();
+    if violation > cst(CONSTRAINT_TOLERANCE) && g_norm2 > T::zero() {
+        let step = -violation / g_norm2;
+        for j in 0..u.len() {
+            u[j] = step * (sign * g[j]) + u[j];
+            if g[j] != T::zero() {
+                active[j] = kind;
+            }
+        }
+        false
+    } else {
+        true
+    }
+}
+
+/// 観測値で修正しながら動かす内部モデルの自由応答の行列
+///
+/// 安定化する場合は A_m - L C_m，オフセットフリーモードでは入力外乱を加えた拡大系の A_z (I - L C_z)．
+fn internal_model_dynamics(a_m: &DMatrix<f64>, b_m: &DMatrix<f64>, c_m: &DMatrix<f64>, observer: Option<&DMatrix<f64>>, stabilizer: Option<&DMatrix<f64>>) -> DMatrix<f64> {
+    let n = a_m.nrows();
+    let l = b_m.ncols();
+    match (observer, stabilizer) {
+        (Some(observer), _) => {
+            let mut a_z = DMatrix::identity(n + l, n + l);
+            a_z.slice_mut((0, 0), (n, n)).copy_from(a_m);
+            a_z.slice_mut((0, n), (n, l)).copy_from(b_m);
+            let mut c_z = DMatrix::zeros(c_m.nrows(), n + l);
+            c_z.slice_mut((0, 0), (c_m.nrows(), n)).copy_from(c_m);
+            &a_z * (DMatrix::identity(n + l, n + l) - observer * c_z)
+        },
+        (None, Some(stabilizer)) => a_m - stabilizer * c_m,
+        (None, None) => a_m.clone(),
+    }
+}
+
+/// 内部モデルの不安定部分を安定化するゲインを計算する．
+///
+/// Aの固有値に絶対値が1以上のもの（不安定・積分要素）が含まれていれば，
+/// 内部モデルを安定部分と不安定部分に分解し，不安定部分だけを
+/// 出力誤差のフィードバック x_m += L (y - C x_m) で安定化する．
+/// A/ρ（ρは安定部分と不安定部分の固有値を分ける半径）に対して
+/// Q = 0 のリカッチ方程式を解くと，|λ| > ρ の固有値だけが ρ^2/λ に移り，
+/// 安定部分の固有値はそのまま残る（安定部分は独立モデルとして働く）．
+///
+///  ------- Arguments -------
+///  * sys: 離散時間状態空間モデル
+///
+///  -------- Return ---------
+///  * L: 安定化ゲイン（全ての固有値が安定なら None）
+///
+/// 不安定部分が出力から観測できなければ安定化できないので Error::NotDetectable を返す．
+fn stabilizing_gain<T: RealField + Copy>(sys: &StateSpace<T>) -> Result<Option<DMatrix<T>>, Error> {
+    let stable_bound = T::one() - cst(UNSTABLE_MARGIN);
+    let eig_abs: Vec<T> = sys.a.complex_eigenvalues().iter().map(|e| e.norm_sqr().sqrt()).collect();
+    if eig_abs.iter().all(|&e| e < stable_bound) {
+        return Ok(None);
+    }
+    check_detectable(&sys.a, &sys.c)?;
+
+    let max_stable = eig_abs.iter().cloned().filter(|&e| e < stable_bound).fold(T::zero(), |a, b| a.max(b));
+    let rho = cst::<T>(0.5) * (max_stable + T::one());
+
+    let n = sys.a.nrows();
+    let p = sys.c.nrows();
+    let a = &sys.a / rho;
+    let r = DMatrix::<T>::identity(p, p);
+    let p_mat = solve_riccati(&a, &sys.c, &DMatrix::<T>::zeros(n, n), &r)?;
+    let s_inv = (&sys.c * &p_mat * sys.c.transpose() + &r).try_inverse()
+        .ok_or(Error::SingularMatrix("innovation covariance of the stabilizing gain"))?;
+    Ok(Some(&sys.a * &p_mat * sys.c.transpose() * s_inv))
+}
+
+/// 絶対値が 1 - UNSTABLE_MARGIN 以上の固有値のモードが全て出力から観測できるか確認する．
+///
+/// PBH判定により，不安定な固有値λ毎に \[λI - A; C\] が列フルランクかを特異値で調べる．
+///
+///  ------- Arguments -------
+///  * a: システム行列
+///  * c: 出力行列
+fn check_detectable<T: RealField + Copy>(a: &DMatrix<T>, c: &DMatrix<T>) -> Result<(), Error> {
+    let n = a.nrows();
+    let p = c.nrows();
+    let stable_bound = T::one() - cst(UNSTABLE_MARGIN);
+    let scale = a.amax().max(c.amax()).max(T::one());
+    for lambda in a.complex_eigenvalues().iter() {
+        if lambda.norm_sqr().sqrt() < stable_bound {
+            continue;
+        }
+        let pbh = DMatrix::<Complex<T>>::from_fn(n + p, n, |i, j| {
+            if i < n {
+                let delta = if i == j {*lambda} else {Complex::new(T::zero(), T::zero())};
+                delta - Complex::new(a[(i, j)], T::zero())
+            } else {
+                Complex::new(c[(i - n, j)], T::zero())
+            }
+        });
+        // 固有値の計算誤差の分は許容する
+        if pbh.singular_values().min() <= scale * T::default_epsilon().sqrt() {
+            return Err(Error::NotDetectable);
+        }
     }
+    Ok(())
+}
+
+/// 離散時間リカッチ方程式（フィルタ形式）を反復して解く．
+///
+/// P = A P A^T - A P C^T (C P C^T + R)^-1 C P A^T + Q
+///
+///  ------- Arguments -------
+///  * a: システム行列
+///  * c: 出力行列
+///  * q: プロセスノイズの共分散行列
+///  * r: 観測ノイズの共分散行列
+///
+/// 反復の上限までに収束しない場合や発散した場合は Error::RiccatiNotConverged を返す．
+fn solve_riccati<T: RealField + Copy>(a: &DMatrix<T>, c: &DMatrix<T>, q: &DMatrix<T>, r: &DMatrix<T>) -> Result<DMatrix<T>, Error> {
+    let n = a.nrows();
+    // 単精度では1e-12まで収束しないので丸め誤差の程度で打ち切る
+    let tol = cst::<T>(1e-12).max(T::default_epsilon() * cst(100.0));
+    let mut p_mat = DMatrix::<T>::identity(n, n);
+    for _ in 0..100000 {
+        let s_inv = (c * &p_mat * c.transpose() + r).try_inverse()
+            .ok_or(Error::SingularMatrix("innovation covariance of the Riccati equation"))?;
+        let p_next = a * (&p_mat - &p_mat * c.transpose() * s_inv * c * &p_mat) * a.transpose() + q;
+        if !p_next.iter().all(|p| p.is_finite()) {
+            return Err(Error::RiccatiNotConverged);
+        }
+        let diff = (&p_next - &p_mat).amax();
+        let scale = p_next.amax().max(T::one());
+        p_mat = p_next;
+        if diff < tol * scale {
+            return Ok(p_mat);
+        }
+    }
+    Err(Error::RiccatiNotConverged)
+}
+
+/// offline_designerの設計結果
+struct Design<T> {
+    k_0: DMatrix<T>,      // 偏差に対するゲイン（入力数×出力数）
+    nu_x: DMatrix<T>,     // 内部モデル状態に対するゲイン（入力数×状態数）
+    nu_r: DMatrix<T>,     // 一致点での目標値の変化分に対するゲイン（入力数×一致点数）
+    condition_number: T,    // 列スケーリング後の基底関数応答行列の条件数
+    basis_responses: DMatrix<T>,   // 各一致点における基底関数に対するモデル出力
+    trajectory_remaining: Vec<f64>,  // 各一致点で参照軌道に残っている偏差の割合
 }
 
 /// オフラインでPFCを設計する
-/// 
+///
+/// 全出力チャネルの一致点における出力増分の誤差二乗和が最小となるように
+/// 全入力チャネルの基底関数の係数をまとめて決める．
+/// 正規方程式のグラム行列は基底関数が増えると悪条件になるので，
+/// 基底関数応答の行列を列毎に正規化してから特異値分解で最小二乗解を求める．
+///
 ///  ------- Arguments -------
 ///  * sys: 離散時間状態空間モデル
-///  * n_h: 一致点の個数
-///  * n_b: 基底関数の個数
-///  * t_clrt: 閉ループ応答時間
-fn offline_designer(sys: &StateSpace<f64>, n_b: usize, n_h: usize, t_clrt: f64) -> (f64, DVector<f64>) {
-    assert!(sys.sample_time > 0.0);
-    assert_ne!(n_b, 0);
-    assert_ne!(n_h, 0);
-    assert!(t_clrt > 0.0);
+///  * inputs: 入力チャネル毎の設計パラメータ
+///  * outputs: 出力チャネル毎の設計パラメータ
+///  * h_points: 出力チャネル毎の一致点のサンプル時刻（むだ時間を除く）
+///  * delay: 入力むだ時間[サンプル]（sysはむだ時間を含めた拡大系）
+///
+///  -------- Return ---------
+///  * 設計したゲインと条件数
+fn offline_designer<T: RealField + Copy>(sys: &StateSpace<T>, inputs: &[InputChannel<T>], outputs: &[OutputChannel<T>], h_points: &[Vec<u32>], delay: u32) -> Result<Design<T>, Error> {
+    if inputs.len() != sys.b.ncols() {
+        return Err(Error::DimensionMismatch("number of input channel parameters does not match the plant"));
+    }
+    if h_points.len() != sys.c.nrows() || outputs.len() != sys.c.nrows() {
+        return Err(Error::DimensionMismatch("number of output channel parameters does not match the plant"));
+    }
+    if inputs.iter().any(|input| input.n_b == 0) {
+        return Err(Error::InvalidParameter("each input channel needs at least one basis function"));
+    }
+    if h_points.iter().any(|points| points.is_empty() || points[0] == 0) {
+        return Err(Error::InvalidCoincidence("each output channel needs coincidence points at least 1 sample ahead"));
+    }
+    if outputs.iter().any(|output| !output.t_clrt.is_finite() || output.t_clrt <= T::zero()) {
+        return Err(Error::InvalidParameter("closed-loop response time must be positive"));
+    }
+    for input in inputs.iter() {
+        input.basis.validate()?;
+    }
 
-    // 参照起動の減衰率
-    let alpha = (-3.0 * sys.sample_time / t_clrt).exp();
+    let n_b_sum: usize = inputs.iter().map(|input| input.n_b).sum();
+    let n_h_sum: usize = h_points.iter().map(|points| points.len()).sum();
+    if n_h_sum < n_b_sum {
+        // 一致点の数が基底関数の数より少ないとグラム行列の階数が足りない
+        return Err(Error::SingularGramMatrix);
+    }
+
+    // 最も遠い一致点までの基底関数の値
+    let h_max = h_points.iter().flatten().max().unwrap() + delay;
+    let basis_values: Vec<DMatrix<T>> = inputs.iter()
+        .map(|input| input.basis.values(input.n_b, h_max).map(|values| values.map(cst)))
+        .collect::<Result<_, _>>()?;
 
     // nuとnu_xの計算に使用する行列
-    let mut tmp0 = DMatrix::<f64>::zeros(n_b, n_h);
-    let mut tmp1 = DMatrix::<f64>::zeros(n_b, n_b);
-    let mut tmp2 = DMatrix::<f64>::zeros(n_h, sys.a.nrows());
-    let mut tmp3 = DVector::<f64>::zeros(n_h);
-    for i in 0..n_h {
-        // 一致点のサンプル時刻
-        let h_time = (t_clrt / (sys.sample_time * (n_h - i) as f64)).floor() as u32;
+    let mut tmp0 = DMatrix::<T>::zeros(n_h_sum, n_b_sum);
+    let mut tmp2 = DMatrix::<T>::zeros(n_h_sum, sys.a.nrows());
+    let mut tmp3 = DMatrix::<T>::zeros(n_h_sum, sys.c.nrows());
+    // むだ時間後のモデル出力 y_m(k+delay) = C A^delay x_m を参照軌道の起点とする
+    let c_delay = &sys.c * sys.a.pow(delay);
+    let mut trajectory_remaining = Vec::with_capacity(n_h_sum);
+    let mut row = 0;
+    for (i, (points, output)) in h_points.iter().zip(outputs.iter()).enumerate() {
+        for &h_time in points {
+            let y_b = calc_y_b(sys, &basis_values, i, h_time + delay);
+            tmp0.set_row(row, &y_b.transpose());
+            // 参照軌道の増分 (1 - φ(h))(r - y - y_m(k+delay) + y_m(k)) と
+            // モデル出力の増分 y_m(k+delay+h) - y_m(k+delay) の差のうち内部モデル状態に掛かる部分
+            let remaining = output.trajectory.remaining(to_f64(sys.sample_time) * h_time as f64, to_f64(output.t_clrt));
+            if !(0.0..=1.0).contains(&remaining) {
+                return Err(Error::InvalidParameter("remaining ratio of the reference trajectory must be in [0, 1]"));
+            }
+            trajectory_remaining.push(remaining);
+            let decay = T::one() - cst(remaining);
+            let free = (&c_delay - &sys.c) * decay + &sys.c * sys.a.pow(h_time + delay) - &c_delay;
+            tmp2.set_row(row, &free.row(i));
+            tmp3[(row, i)] = decay;
+            row += 1;
+        }
+    }
 
-        let y_b = calc_y_b(&sys, n_b, h_time);
-        tmp0.set_column(i, &y_b);
-        tmp1 += y_b.clone() * y_b.transpose();
-        tmp2.set_row(i, &( sys.c.clone() * sys.a.pow(h_time) - sys.c.clone() ).row(0));
-        tmp3[i] = 1.0 - alpha.powi(h_time as i32);
+    // 列毎に正規化して特異値分解し，擬似逆行列を求める
+    let mut scale = DVector::<T>::zeros(n_b_sum);
+    for (l, col) in tmp0.column_iter().enumerate() {
+        let norm = col.norm();
+        if norm == T::zero() {
+            return Err(Error::SingularGramMatrix);
+        }
+        scale[l] = T::one() / norm;
     }
-    let nu = tmp0.transpose() * tmp1.try_inverse().unwrap().column(0);
-    let k_0 = nu.transpose() * tmp3;
-    let nu_x = -tmp2.transpose() * nu;
+    let tmp0_scaled = &tmp0 * DMatrix::from_diagonal(&scale);
+    let svd = tmp0_scaled.svd(true, true);
+    let sigma_max = svd.singular_values.max();
+    let sigma_min = svd.singular_values.min();
+    if sigma_min <= sigma_max * cst(n_h_sum as f64) * T::default_epsilon() {
+        return Err(Error::SingularGramMatrix);
+    }
+    let condition_number = sigma_max / sigma_min;
+    let tmp0_pinv = DMatrix::from_diagonal(&scale) * svd.pseudo_inverse(T::zero()).map_err(|_| Error::SingularGramMatrix)?;
+
+    // 各入力チャネルの現在時刻の入力は Σ_l μ_l B_l(0)
+    let mut nu = DMatrix::<T>::zeros(inputs.len(), n_h_sum);
+    let mut offset = 0;
+    for (j, input) in inputs.iter().enumerate() {
+        let b_0 = basis_values[j].column(0);
+        nu.set_row(j, &(b_0.transpose() * tmp0_pinv.rows(offset, input.n_b)));
+        offset += input.n_b;
+    }
+    let k_0 = &nu * tmp3;
+    let nu_x = -&nu * tmp2;
+
+    Ok(Design {k_0, nu_x, nu_r: nu, condition_number, basis_responses: tmp0, trajectory_remaining})
+}
 
-    (k_0[0], nu_x)
+/// 一致点のサンプル時刻を計算する．
+///
+///  ------- Arguments -------
+///  * sample_time: 離散化周期
+///  * n_h: 一致点の個数
+///  * t_clrt: 閉ループ応答時間
+fn coincidence_points<T: RealField + Copy>(sample_time: T, n_h: usize, t_clrt: T) -> Vec<u32> {
+    (0..n_h).map(|j| to_f64((t_clrt / (sample_time * cst((n_h - j) as f64))).floor()) as u32).collect()
 }
 
 /// 一致点における各基底関数に対するモデル出力を
 /// まとめたベクトルを計算する．
-/// 
+///
+/// 入力チャネル順に各チャネルの基底関数の応答を並べる．
+///
 ///  ------- Arguments -------
 ///  * sys: 離散時間状態空間モデル
-///  * n_b: 基底関数の個数
+///  * basis_values: 入力チャネル毎の基底関数の値（(l, q) 要素が B_l(q)）
+///  * i: 出力チャネル
 ///  * h_j: 一致点のサンプル時刻
-fn calc_y_b(sys: &StateSpace<f64>, n_b: usize, h_j: u32) -> DVector<f64> {    
-    let mut y_b = DVector::<f64>::zeros(n_b);
-    for l in 0..n_b as u32 {
-        let mut y_bl = 0.0;
-        let tmp = h_j - 1;
-        for q in 0..h_j {
-            let a_pow = sys.a.pow(tmp - q);
-            let coef = q.pow(l) as f64;
-            y_bl += (sys.c.clone() * a_pow * sys.b.clone() * coef)[0];
-        }
-        y_b[l as usize] = y_bl;
+fn calc_y_b<T: RealField + Copy>(sys: &StateSpace<T>, basis_values: &[DMatrix<T>], i: usize, h_j: u32) -> DVector<T> {
+    let mut y_b = DVector::<T>::zeros(basis_values.iter().map(|v| v.nrows()).sum());
+    let tmp = h_j - 1;
+
+    // 入力チャネルjのインパルス応答 (C A^(h_j-1-q) B)_ij
+    let mut impulse = DMatrix::<T>::zeros(basis_values.len(), h_j as usize);
+    for q in 0..h_j {
+        impulse.set_column(q as usize, &(&sys.c * sys.a.pow(tmp - q) * &sys.b).row(i).transpose());
+    }
+
+    let mut offset = 0;
+    for (j, values) in basis_values.iter().enumerate() {
+        for l in 0..values.nrows() {
+            let mut y_bl = T::zero();
+            for q in 0..h_j as usize {
+                y_bl += impulse[(j, q)] * values[(l, q)];
+            }
+            y_b[offset + l] = y_bl;
+        }
+        offset += values.nrows();
     }
     y_b
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis;
+    use crate::test_plants::{mass_spring_damper, simulate, two_mass, SAMPLE_TIME};
+    use nalgebra::SVector;
+    use pfc_runtime::fixed::Q16;
+    use pfc_runtime::format::FormatError;
+
+    /// 2質点系に対する2入力2出力のPFC
+    fn two_mass_pfc() -> PFC {
+        PFC::new(&two_mass(), &[InputChannel::new([-50.0, 50.0]); 2], &[OutputChannel::new(1.0), OutputChannel::new(1.5)]).unwrap()
+    }
+
+    #[test]
+    fn mimo_gain_shapes() {
+        let pfc = two_mass_pfc();
+        let report = pfc.report();
+        assert_eq!(report.k_0.shape(), (2, 2));
+        assert_eq!(report.nu_x.shape(), (2, 4));
+        assert_eq!(report.nu_r.shape(), (2, 6));
+        assert_eq!(report.horizons.iter().filter(|&&(i, _)| i == 0).count(), 3);
+        assert_eq!(report.horizons.iter().filter(|&&(i, _)| i == 1).count(), 3);
+        // 結合があるので偏差に対するゲインは対角にならない
+        assert!(report.k_0[(0, 1)].abs() > 1e-6 && report.k_0[(1, 0)].abs() > 1e-6);
+    }
+
+    #[test]
+    fn mimo_tracks_setpoint_without_steady_state_error() {
+        let plant = two_mass();
+        let mut pfc = two_mass_pfc();
+        let r = vec![DVector::from_column_slice(&[0.1, -0.05]); 400];
+        let log = simulate(&plant, &mut pfc, &r, false, |_| DVector::zeros(2));
+        let (y, _) = log.last().unwrap();
+        assert!((y - &r[0]).amax() < 1e-6, "y = {}", y);
+
+        let closed_loop = analysis::closed_loop(&plant, &pfc).unwrap();
+        assert!(closed_loop.is_stable());
+        assert!((&closed_loop.dc_gain - DMatrix::<f64>::identity(2, 2)).amax() < 1e-6, "dc gain = {}", closed_loop.dc_gain);
+    }
+
+    /// 1入力1出力の制御対象に対する一致点3個・閉ループ応答時間0.5 [s]のPFC
+    fn siso_pfc(plant: &StateSpace<f64>, n_b: usize) -> PFC {
+        PFC::new(plant, &[InputChannel {n_b, ..InputChannel::new([-50.0, 50.0])}], &[OutputChannel::new(0.5)]).unwrap()
+    }
+
+    /// バネ・マス・ダンパ系（m = 5, c = 5, k = 5）に対するPFC
+    fn msd_pfc(n_b: usize) -> PFC {
+        siso_pfc(&mass_spring_damper(5.0, 5.0, 5.0), n_b)
+    }
+
+    #[test]
+    fn output_limit_bounds_the_response() {
+        let plant = mass_spring_damper(5.0, 5.0, 5.0);
+        let mut pfc = msd_pfc(2);
+        pfc.set_output_limit(Some(&[[-1.0, 0.1]])).unwrap();
+        // 目標値は出力制約の外
+        let r = vec![DVector::from_element(1, 0.2); 200];
+        let log = simulate(&plant, &mut pfc, &r, false, |_| DVector::zeros(1));
+        let y_max = log.iter().map(|(y, _)| y[0]).fold(f64::MIN, f64::max);
+        assert!(y_max < 0.1 + 1e-3, "y_max = {}", y_max);
+        assert!(log.last().unwrap().0[0] > 0.09);
+        assert!(pfc.is_feasible());
+    }
+
+    #[test]
+    fn state_limit_bounds_the_velocity() {
+        let plant = mass_spring_damper(5.0, 5.0, 5.0);
+        let mut pfc = msd_pfc(2);
+        pfc.set_state_limit(Some(&[(1, [-0.05, 0.05])])).unwrap();
+        let r = vec![DVector::from_element(1, 0.1); 200];
+        let mut x = DVector::zeros(2);
+        let mut v_max: f64 = 0.0;
+        for r_k in r.iter() {
+            let y = plant.c() * &x;
+            let u = pfc.update(r_k, &y);
+            x = plant.a() * &x + plant.b() * &u;
+            v_max = v_max.max(x[1].abs());
+        }
+        assert!(v_max < 0.05 + 1e-3, "v_max = {}", v_max);
+        assert!((x[0] - 0.1).abs() < 1e-4);
+    }
+
+    #[test]
+    fn conflicting_limits_are_infeasible() {
+        let mut pfc = msd_pfc(2);
+        // 一致点までに出力を0.2以上にするには速度制約を大きく超える必要がある
+        pfc.set_output_limit(Some(&[[0.2, 0.3]])).unwrap();
+        pfc.set_state_limit(Some(&[(1, [-1e-3, 1e-3])])).unwrap();
+        let u = pfc.update(&DVector::from_element(1, 0.25), &DVector::zeros(1));
+        assert!(!pfc.is_feasible());
+        assert!(u[0].is_finite());
+        assert!(pfc.active_constraint().iter().any(|&c| c != ActiveConstraint::None));
+    }
+
+    #[test]
+    fn rate_limit_bounds_input_change_and_drives_the_internal_model() {
+        let plant = mass_spring_damper(5.0, 5.0, 5.0);
+        let mut pfc = msd_pfc(2);
+        pfc.set_rate_limit(Some(&[[-2.0, 2.0]])).unwrap();
+        // 目標値を0.1 [m]に上げてから0 [m]に戻す
+        let r: Vec<DVector<f64>> = (0..800).map(|k| DVector::from_element(1, if k < 400 {0.1} else {0.0})).collect();
+        let mut x = DVector::zeros(2);
+        let mut u_prev = 0.0;
+        let mut reported = Vec::new();
+        for r_k in r.iter() {
+            let y = plant.c() * &x;
+            let x_m = Controller::state(&pfc);
+            let u = pfc.update(r_k, &y);
+            assert!((u[0] - u_prev).abs() <= 2.0 + 1e-12, "du = {}", u[0] - u_prev);
+            // 内部モデルは制約を掛けた後の入力で更新される
+            assert!((Controller::state(&pfc) - (plant.a() * x_m + plant.b() * &u)).amax() < 1e-12);
+            reported.push(pfc.active_constraint()[0]);
+            x = plant.a() * &x + plant.b() * &u;
+            u_prev = u[0];
+        }
+        assert_eq!(reported[0], ActiveConstraint::RateUpper);
+        assert_eq!(reported[400], ActiveConstraint::RateLower);
+        assert_eq!(reported[399], ActiveConstraint::None);
+        assert!(x[0].abs() < 1e-6, "y = {}", x[0]);
+    }
+
+    #[test]
+    fn input_limit_that_breaks_output_limit_is_infeasible() {
+        // 出力の下限0.05に一致点で届くには入力上限1 [N]では足りない
+        let mut pfc = msd_pfc(2);
+        pfc.set_output_limit(Some(&[[0.05, 1.0]])).unwrap();
+        pfc.set_limit(&[[-1.0, 1.0]]).unwrap();
+        let u = pfc.update(&DVector::from_element(1, 0.1), &DVector::zeros(1));
+        assert_eq!(u[0], 1.0);
+        assert_eq!(pfc.active_constraint(), &[ActiveConstraint::Upper]);
+        assert!(!pfc.is_feasible());
+
+        // 入力制約が十分広ければ満たせる
+        let mut pfc = msd_pfc(2);
+        pfc.set_output_limit(Some(&[[0.05, 1.0]])).unwrap();
+        pfc.update(&DVector::from_element(1, 0.1), &DVector::zeros(1));
+        assert!(pfc.is_feasible());
+    }
+
+    #[test]
+    fn limit_setters_validate_dimensions() {
+        let mut pfc = msd_pfc(2);
+        assert!(matches!(pfc.set_limit(&[[-1.0, 1.0], [-1.0, 1.0]]), Err(Error::DimensionMismatch(_))));
+        assert!(matches!(pfc.set_limit(&[[1.0, -1.0]]), Err(Error::InvalidParameter(_))));
+        assert!(matches!(pfc.set_limit(&[]), Err(Error::DimensionMismatch(_))));
+        assert!(matches!(pfc.set_rate_limit(Some(&[])), Err(Error::DimensionMismatch(_))));
+        assert!(matches!(pfc.set_output_limit(Some(&[[-1.0, 1.0], [-1.0, 1.0]])), Err(Error::DimensionMismatch(_))));
+        assert!(matches!(pfc.set_output_limit(Some(&[[f64::NAN, 1.0]])), Err(Error::InvalidParameter(_))));
+        assert!(matches!(pfc.set_state_limit(Some(&[(2, [-1.0, 1.0])])), Err(Error::DimensionMismatch(_))));
+        // 失敗した設定は反映されない
+        assert_eq!(pfc.limit(), &[[-50.0, 50.0]]);
+        assert!(pfc.output_limit().is_none() && pfc.state_limit().is_none());
+
+        pfc.set_state_limit(Some(&[(1, [-0.1, 0.1])])).unwrap();
+        pfc.set_rate_limit(Some(&[[-1.0, 1.0]])).unwrap();
+        pfc.update(&DVector::from_element(1, 1.0), &DVector::zeros(1));
+        assert_eq!(pfc.state_limit(), Some(&[(1, [-0.1, 0.1])][..]));
+    }
+
+    #[test]
+    fn offset_free_rejects_input_disturbance_and_model_error() {
+        // モデルのバネ定数は3 [N/m]，実際は5 [N/m]で，100サンプル目から-1 [N]の入力外乱が加わる
+        let plant = mass_spring_damper(5.0, 5.0, 5.0);
+        let mut pfc = siso_pfc(&mass_spring_damper(5.0, 5.0, 3.0), 2);
+        pfc.enable_offset_free(1e-6, 1e-2, 1e-6).unwrap();
+        let r = vec![DVector::from_element(1, 0.1); 600];
+        let log = simulate(&plant, &mut pfc, &r, false, |k| DVector::from_element(1, if k >= 100 {-1.0} else {0.0}));
+        let offset = (log.last().unwrap().0[0] - 0.1).abs();
+        assert!(offset < 1e-9, "offset = {}", offset);
+    }
+
+    #[test]
+    fn offset_free_removes_offset_of_integrating_plant() {
+        // 積分要素を含む制御対象（k = 0）に-1 [N]の入力外乱が加わる
+        let plant = mass_spring_damper(5.0, 5.0, 0.0);
+        let r = vec![DVector::from_element(1, 0.1); 600];
+        let disturbance = |k: usize| DVector::from_element(1, if k >= 100 {-1.0} else {0.0});
+
+        let mut pfc = siso_pfc(&plant, 2);
+        let log = simulate(&plant, &mut pfc, &r, false, disturbance);
+        let offset = (log.last().unwrap().0[0] - 0.1).abs();
+        assert!(offset > 1e-2, "offset = {}", offset);
+
+        let mut pfc = siso_pfc(&plant, 2);
+        pfc.enable_offset_free(1e-6, 1e-2, 1e-6).unwrap();
+        let log = simulate(&plant, &mut pfc, &r, false, disturbance);
+        let offset = (log.last().unwrap().0[0] - 0.1).abs();
+        assert!(offset < 1e-9, "offset = {}", offset);
+        assert!((pfc.disturbance()[0] + 1.0).abs() < 1e-6, "d = {}", pfc.disturbance()[0]);
+    }
+
+    #[test]
+    fn integrating_and_unstable_plants_are_stabilized() {
+        // k = 0 は積分要素，k < 0 は不安定
+        for k in [0.0, -5.0] {
+            let plant = mass_spring_damper(5.0, 5.0, k);
+            let mut pfc = siso_pfc(&plant, 2);
+            assert!(pfc.is_decomposed());
+            assert!(analysis::closed_loop(&plant, &pfc).unwrap().is_stable());
+
+            let r = vec![DVector::from_element(1, 0.1); 400];
+            let log = simulate(&plant, &mut pfc, &r, false, |_| DVector::zeros(1));
+            let offset = (log.last().unwrap().0[0] - 0.1).abs();
+            assert!(offset < 1e-6, "k = {}, offset = {}", k, offset);
+        }
+        assert!(!msd_pfc(2).is_decomposed());
+    }
+
+    #[test]
+    fn undetectable_unstable_mode_is_an_error() {
+        // 不安定な固有値1.1のモードが出力に現れない
+        let plant = StateSpace::new(
+            DMatrix::from_row_slice(2, 2, &[1.1, 0.0, 0.0, 0.5]),
+            DMatrix::from_row_slice(2, 1, &[1.0, 1.0]),
+            DMatrix::from_row_slice(1, 2, &[0.0, 1.0]),
+            DMatrix::zeros(1, 1),
+            0.05,
+        ).unwrap();
+        let result = PFC::new(&plant, &[InputChannel {n_b: 1, ..InputChannel::new([-50.0, 50.0])}], &[OutputChannel::new(0.5)]);
+        assert!(matches!(result, Err(Error::NotDetectable)));
+    }
+
+    #[test]
+    fn dead_time_is_compensated_like_a_smith_predictor() {
+        let plant = mass_spring_damper(5.0, 5.0, 5.0);
+        let r = vec![DVector::from_element(1, 0.1); 400];
+        let mut pfc = msd_pfc(2);
+        let y_0: Vec<f64> = simulate(&plant, &mut pfc, &r, false, |_| DVector::zeros(1)).iter().map(|(y, _)| y[0]).collect();
+        for delay in [1, 3, 8] {
+            let plant_d = plant.clone().with_delay(delay);
+            let mut pfc = siso_pfc(&plant_d, 2);
+            assert!(analysis::closed_loop(&plant_d, &pfc).unwrap().is_stable(), "delay = {}", delay);
+            let log = simulate(&plant_d.delay_augmented(), &mut pfc, &r, false, |_| DVector::zeros(1));
+            let y_d: Vec<f64> = log.iter().map(|(y, _)| y[0]).collect();
+            let diff = (0..(400 - delay)).map(|k| (y_d[k + delay] - y_0[k]).abs()).fold(0.0, f64::max);
+            // 公称モデルではむだ時間のない応答をむだ時間だけ遅らせたものになる
+            assert!(diff < 1e-12, "delay = {}, diff = {}", delay, diff);
+            assert!((y_d[399] - 0.1).abs() < 1e-9, "delay = {}, y = {}", delay, y_d[399]);
+        }
+    }
+
+    #[test]
+    fn preview_removes_ramp_tracking_error() {
+        let plant = mass_spring_damper(5.0, 5.0, 5.0);
+        let r: Vec<DVector<f64>> = (0..600).map(|k| DVector::from_element(1, 0.02 * SAMPLE_TIME * k as f64)).collect();
+        // 系列の終端では先読みできないので十分手前で比べる
+        let error = |preview: bool| {
+            let mut pfc = msd_pfc(2);
+            let log = simulate(&plant, &mut pfc, &r, preview, |_| DVector::zeros(1));
+            (log[400].0[0] - r[400][0]).abs()
+        };
+        assert!(error(true) < 1e-9, "error = {}", error(true));
+        assert!(error(false) > 1e-3, "error = {}", error(false));
+    }
+
+    #[test]
+    fn empty_sequence_holds_previous_input() {
+        let mut pfc = msd_pfc(2);
+        let y = DVector::zeros(1);
+        let u = pfc.update(&DVector::from_element(1, 0.1), &y);
+        let held = pfc.update_with_preview(&Setpoint::Sequence(&[]), &y);
+        assert_eq!(held, u);
+        let held = pfc.update_with_preview(&Setpoint::Polynomial(&[]), &y);
+        assert_eq!(held, u);
+    }
+
+    /// 一致点の決め方だけを変えたPFCを設計する
+    fn design_with(plant: &StateSpace<f64>, coincidence: Coincidence, t_clrt: f64) -> Result<PFC, Error> {
+        PFC::new(plant, &[InputChannel {n_b: 1, ..InputChannel::new([-50.0, 50.0])}], &[OutputChannel {coincidence, ..OutputChannel::new(t_clrt)}])
+    }
+
+    #[test]
+    fn explicit_coincidence_points_are_used() {
+        let plant = mass_spring_damper(5.0, 5.0, 5.0);
+        let pfc = design_with(&plant, Coincidence::Explicit(vec![2, 5, 9]), 0.5).unwrap();
+        assert_eq!(pfc.report().horizons, vec![(0, 2), (0, 5), (0, 9)]);
+    }
+
+    #[test]
+    fn invalid_coincidence_points_are_errors() {
+        let plant = mass_spring_damper(5.0, 5.0, 5.0);
+        for points in [vec![], vec![0, 3], vec![3, 3], vec![5, 2]] {
+            let result = design_with(&plant, Coincidence::Explicit(points.clone()), 0.5);
+            assert!(matches!(result, Err(Error::InvalidCoincidence(_))), "points = {:?}", points);
+        }
+        // floor(0.1 / 0.15) = 0 サンプル目の一致点
+        assert!(matches!(design_with(&plant, Coincidence::Uniform(3), 0.1), Err(Error::InvalidCoincidence(_))));
+        // floor(0.25 / 0.2) = floor(0.25 / 0.15) = 1 で重複する
+        assert!(matches!(design_with(&plant, Coincidence::Uniform(4), 0.25), Err(Error::InvalidCoincidence(_))));
+        assert!(matches!(design_with(&plant, Coincidence::Uniform(3), -0.5), Err(Error::InvalidParameter(_))));
+        // 遠すぎる一致点（むだ時間を含めて数える）
+        assert!(matches!(design_with(&plant, Coincidence::Explicit(vec![1, u32::MAX]), 0.5), Err(Error::InvalidCoincidence(_))));
+        assert!(matches!(design_with(&plant.with_delay(MAX_HORIZON), Coincidence::Explicit(vec![1]), 0.5), Err(Error::InvalidCoincidence(_))));
+    }
+
+    #[test]
+    fn dominant_time_constant_places_points() {
+        // 極は s = -0.5 ± 0.866j なので支配的な時定数は 2 [s] = 40サンプル
+        let plant = mass_spring_damper(5.0, 5.0, 5.0);
+        let pfc = design_with(&plant, Coincidence::DominantTimeConstant(3), 0.5).unwrap();
+        assert_eq!(pfc.report().horizons, vec![(0, 14), (0, 27), (0, 40)]);
+
+        // 安定な極のない制御対象（c = k = 0）では決められない
+        let plant = mass_spring_damper(5.0, 0.0, 0.0);
+        assert!(matches!(design_with(&plant, Coincidence::DominantTimeConstant(3), 0.5), Err(Error::InvalidCoincidence(_))));
+    }
+
+    #[test]
+    fn many_polynomial_basis_functions_remain_usable() {
+        let plant = mass_spring_damper(5.0, 5.0, 5.0);
+        let design = |n_b: usize, n_h: usize| PFC::new(
+            &plant, &[InputChannel {n_b, ..InputChannel::new([-50.0, 50.0])}],
+            &[OutputChannel {coincidence: Coincidence::Uniform(n_h), ..OutputChannel::new(1.0)}],
+        );
+        let condition_numbers: Vec<f64> = (1..=5).map(|n_b| design(n_b, 6).unwrap().report().condition_number).collect();
+        assert!(condition_numbers.windows(2).all(|w| w[0] < w[1]), "{:?}", condition_numbers);
+        assert!(condition_numbers[4] < 1e8, "{:?}", condition_numbers);
+
+        let mut pfc = design(5, 6).unwrap();
+        assert!(pfc.report().k_0.iter().chain(pfc.report().nu_x.iter()).all(|g| g.is_finite()));
+        let r = vec![DVector::from_element(1, 0.1); 400];
+        let log = simulate(&plant, &mut pfc, &r, false, |_| DVector::zeros(1));
+        assert!((log.last().unwrap().0[0] - 0.1).abs() < 1e-6);
+        assert!(analysis::closed_loop(&plant, &pfc).unwrap().is_stable());
+
+        // 一致点が基底関数より少なければ最小二乗解が一意に決まらない
+        assert!(matches!(design(5, 4), Err(Error::SingularGramMatrix)));
+    }
+
+    #[test]
+    fn serialized_gains_round_trip() {
+        let mut pfc = PFC::new(&mass_spring_damper(5.0, 5.0, 5.0).with_delay(3), &[InputChannel::new([-5.0, 5.0])], &[OutputChannel::new(0.5)]).unwrap();
+        pfc.set_rate_limit(Some(&[[-2.0, 2.0]])).unwrap();
+        pfc.enable_offset_free(1e-6, 1e-2, 1e-6).unwrap();
+
+        let expected = pfc.to_static::<5, 1, 1, 3>().unwrap();
+        let loaded = StaticPFC::<f64, 5, 1, 1, 3>::from_bytes(&pfc.serialize_gains().unwrap()).unwrap();
+        assert_eq!(loaded.gains(), expected.gains());
+        assert_eq!(loaded.limit, expected.limit);
+        assert_eq!(loaded.rate_limit, expected.rate_limit);
+
+        // 静的サイズのPFCは出力・状態制約を扱わない
+        pfc.set_state_limit(Some(&[(1, [-0.15, 0.15])])).unwrap();
+        assert!(matches!(pfc.serialize_gains(), Err(Error::InvalidParameter(_))));
+    }
+
+    #[test]
+    fn single_precision_design_tracks_like_double_precision() {
+        // むだ時間のある制御対象と積分要素を含む制御対象（内部モデルを分解する）をオフセットフリーモードで制御する
+        for (plant, decomposed) in [(mass_spring_damper(5.0, 5.0, 5.0).with_delay(3), false), (mass_spring_damper(5.0, 5.0, 0.0), true)] {
+            let inputs = [InputChannel {n_b: 2, ..InputChannel::new([-50.0, 50.0])}];
+            let outputs = [OutputChannel::new(0.5)];
+            let mut pfc = PFC::new(&plant, &inputs, &outputs).unwrap();
+            pfc.enable_offset_free(1e-6, 1e-2, 1e-6).unwrap();
+            let inputs_f32 = [InputChannel {n_b: 2, ..InputChannel::new([-50.0f32, 50.0])}];
+            let outputs_f32 = [OutputChannel::new(0.5f32)];
+            let mut pfc_f32 = PFC::new(&plant.cast::<f32>(), &inputs_f32, &outputs_f32).unwrap();
+            pfc_f32.enable_offset_free(1e-6, 1e-2, 1e-6).unwrap();
+            assert_eq!(pfc_f32.is_decomposed(), decomposed);
+
+            // 同じ制御対象（倍精度）を別々に制御し，100サンプル目から-1 [N]の入力外乱を加える
+            let sim = plant.delay_augmented();
+            let r = DVector::from_element(1, 0.1);
+            let r_f32 = r.map(|v| v as f32);
+            let mut x = DVector::zeros(sim.a().nrows());
+            let mut x_f32 = x.clone();
+            let mut drift: f64 = 0.0;
+            let mut y_f32 = DVector::zeros(1);
+            for k in 0..600 {
+                let d = DVector::from_element(1, if k >= 100 {-1.0} else {0.0});
+                let u = pfc.update(&r, &(sim.c() * &x));
+                y_f32 = sim.c() * &x_f32;
+                let u_f32 = pfc_f32.update(&r_f32, &y_f32.map(|v| v as f32)).map(|v| v as f64);
+                drift = drift.max((&u - &u_f32).amax());
+                x = sim.a() * &x + sim.b() * (u + &d);
+                x_f32 = sim.a() * &x_f32 + sim.b() * (u_f32 + &d);
+            }
+            let offset = (y_f32[0] - 0.1).abs();
+            assert!(offset < 1e-6, "decomposed = {}, offset = {}", decomposed, offset);
+            assert!(drift < 5e-3, "decomposed = {}, drift = {}", decomposed, drift);
+            assert!((pfc_f32.disturbance()[0] + 1.0).abs() < 1e-4, "d = {}", pfc_f32.disturbance()[0]);
+        }
+    }
+
+    /// 動的サイズのPFCと，それを変換した静的サイズのPFCで同じ制御対象を制御し，制御入力が一致することを確かめる．
+    ///
+    /// 目標値は20サンプル目からのステップを先読みさせ，200サンプル目から-1 [N]の入力外乱を加える．
+    fn assert_static_matches_dynamic<const N: usize>(plant: &StateSpace<f64>, mut pfc: PFC) {
+        let mut pfc_static = pfc.to_static::<N, 1, 1, 3>().unwrap();
+        let sim = plant.delay_augmented();
+        let r: Vec<DVector<f64>> = (0..400)
+            .map(|k| DVector::from_element(1, if k >= 20 {0.1} else {0.0}))
+            .collect();
+        let r_static: Vec<SVector<f64, 1>> = r.iter().map(|r| SVector::<f64, 1>::new(r[0])).collect();
+        let mut x = DVector::zeros(sim.a().nrows());
+        let mut x_static = x.clone();
+        let mut n_active = 0;
+        for k in 0..r.len() {
+            let d = DVector::from_element(1, if k >= 200 {-1.0} else {0.0});
+            let u = pfc.update_with_preview(&Setpoint::Sequence(&r[k..]), &(sim.c() * &x));
+            let y_static = SVector::<f64, 1>::new((sim.c() * &x_static)[0]);
+            let u_static = pfc_static.update_with_preview(&r_static[k..], &y_static);
+            assert!((u[0] - u_static[0]).abs() < 1e-9, "k = {}, u = {}, u_static = {}", k, u[0], u_static[0]);
+            assert_eq!(pfc.active_constraint(), &pfc_static.active_constraint()[..], "k = {}", k);
+            if pfc.active_constraint()[0] != ActiveConstraint::None {
+                n_active += 1;
+            }
+            if k == 199 {
+                // 外乱が入る前に目標値に追従している
+                assert!((y_static[0] - 0.1).abs() < 1e-3, "y = {}", y_static[0]);
+            }
+            x = sim.a() * &x + sim.b() * (u + &d);
+            x_static = sim.a() * &x_static + sim.b() * (DVector::from_element(1, u_static[0]) + &d);
+        }
+        assert!(n_active > 0);
+        assert!((pfc.disturbance()[0] - pfc_static.disturbance()[0]).abs() < 1e-9);
+    }
+
+    #[test]
+    fn static_pfc_matches_dynamic_pfc() {
+        let design = |plant: &StateSpace<f64>, offset_free: bool| {
+            let mut pfc = PFC::new(plant, &[InputChannel::new([-5.0, 5.0])], &[OutputChannel::new(0.5)]).unwrap();
+            pfc.set_rate_limit(Some(&[[-2.0, 2.0]])).unwrap();
+            if offset_free {
+                pfc.enable_offset_free(1e-6, 1e-2, 1e-6).unwrap();
+            }
+            pfc
+        };
+
+        // むだ時間を含む内部モデル（オフセットフリーモード）
+        let plant = mass_spring_damper(5.0, 5.0, 5.0).with_delay(3);
+        assert_static_matches_dynamic::<5>(&plant, design(&plant, true));
+
+        // 積分要素を含む制御対象は内部モデルを分解して安定化する（オフセットフリーモードの有無とも）
+        let plant = mass_spring_damper(5.0, 5.0, 0.0);
+        for offset_free in [false, true] {
+            let pfc = design(&plant, offset_free);
+            assert!(pfc.is_decomposed());
+            assert!(pfc.to_static::<2, 1, 1, 3>().unwrap().gains().stabilizer.is_some());
+            assert_static_matches_dynamic::<2>(&plant, pfc);
+        }
+    }
+
+    /// 固定小数点数の実行部に載せるPFC（むだ時間3サンプル，変化率制約，オフセットフリーモード）
+    fn firmware_pfc() -> PFC {
+        let mut pfc = PFC::new(&mass_spring_damper(5.0, 5.0, 5.0).with_delay(3), &[InputChannel::new([-5.0, 5.0])], &[OutputChannel::new(0.5)]).unwrap();
+        pfc.set_rate_limit(Some(&[[-2.0, 2.0]])).unwrap();
+        pfc.enable_offset_free(1e-6, 1e-2, 1e-6).unwrap();
+        pfc
+    }
+
+    #[test]
+    fn quantization_errors_are_within_half_lsb() {
+        let report = firmware_pfc().quantize::<16>().unwrap();
+        let half_lsb = 0.5 / 65536.0;
+        for (name, error) in [("k_0", report.k_0), ("nu_x", report.nu_x), ("nu_r", report.nu_r), ("a_m", report.a_m), ("b_m", report.b_m)] {
+            assert!(error <= half_lsb, "{} = {}", name, error);
+        }
+        assert!(report.nu_r > 0.0);
+        assert!((report.quantized_spectral_radius - report.spectral_radius).abs() < 1e-3);
+        assert!(report.is_stable());
+
+        // 小数部が2bitでは A_m の固有値が単位円上に丸められる
+        let report = msd_pfc(2).quantize::<2>().unwrap();
+        assert!(report.spectral_radius < 1.0);
+        assert!(!report.is_stable(), "{}", report.quantized_spectral_radius);
+    }
+
+    #[test]
+    fn quantize_checks_every_serialized_value() {
+        // 入力制約・変化率制約もQ16で表せなければ，実行部で読み込めないのでエラー
+        let mut pfc = firmware_pfc();
+        pfc.set_limit(&[[-5e4, 5e4]]).unwrap();
+        assert!(matches!(pfc.quantize::<16>(), Err(Error::InvalidParameter(_))));
+        let gains = pfc.serialize_gains().unwrap();
+        assert_eq!(StaticPFC::<Q16, 5, 1, 1, 3>::from_bytes(&gains).unwrap_err(), FormatError::InvalidValue);
+
+        let mut pfc = firmware_pfc();
+        pfc.set_rate_limit(Some(&[[-5e4, 5e4]])).unwrap();
+        assert!(matches!(pfc.quantize::<16>(), Err(Error::InvalidParameter(_))));
+        let gains = pfc.serialize_gains().unwrap();
+        assert_eq!(StaticPFC::<Q16, 5, 1, 1, 3>::from_bytes(&gains).unwrap_err(), FormatError::InvalidValue);
+
+        let pfc = firmware_pfc();
+        assert!(pfc.quantize::<16>().is_ok());
+        assert!(StaticPFC::<Q16, 5, 1, 1, 3>::from_bytes(&pfc.serialize_gains().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn q16_static_pfc_tracks_like_f64() {
+        // 同じゲインを倍精度とQ16で読み込み，同じ制御対象（倍精度）を別々に制御する
+        let plant = mass_spring_damper(5.0, 5.0, 5.0).with_delay(3).delay_augmented();
+        let gains = firmware_pfc().serialize_gains().unwrap();
+        let mut pfc_float = StaticPFC::<f64, 5, 1, 1, 3>::from_bytes(&gains).unwrap();
+        let mut pfc_fixed = StaticPFC::<Q16, 5, 1, 1, 3>::from_bytes(&gains).unwrap();
+        let r = SVector::<f64, 1>::new(0.1);
+        let r_fixed = r.map(|v| Q16::from_f64(v).unwrap());
+        let mut x_float = DVector::zeros(5);
+        let mut x_fixed = DVector::zeros(5);
+        let (mut diff_u, mut diff_y): (f64, f64) = (0.0, 0.0);
+        let mut y_fixed = 0.0;
+        for k in 0..600 {
+            // 300サンプル目から-1 [N]の入力外乱
+            let d = if k >= 300 {-1.0} else {0.0};
+            let y_float = (plant.c() * &x_float)[0];
+            y_fixed = (plant.c() * &x_fixed)[0];
+            let u_float = pfc_float.update(&r, &SVector::<f64, 1>::new(y_float))[0];
+            let u_fixed = pfc_fixed.update(&r_fixed, &SVector::<Q16, 1>::new(Q16::from_f64(y_fixed).unwrap()))[0].to_f64();
+            diff_u = diff_u.max((u_float - u_fixed).abs());
+            diff_y = diff_y.max((y_float - y_fixed).abs());
+            x_float = plant.a() * &x_float + plant.b() * (u_float + d);
+            x_fixed = plant.a() * &x_fixed + plant.b() * (u_fixed + d);
+        }
+        assert!(diff_u < 0.05, "diff_u = {}", diff_u);
+        assert!(diff_y < 5e-4, "diff_y = {}", diff_y);
+        // 外乱を除去して目標値に戻る（Q16の分解能は1.5e-5程度）
+        assert!((y_fixed - 0.1).abs() < 1e-4, "y = {}", y_fixed);
+        assert!((pfc_fixed.disturbance()[0].to_f64() + 1.0).abs() < 1e-2);
+    }
+}
@@ -0,0 +1,47 @@
+//! 状態空間モデルの作成とPFCの設計で発生するエラー
+
+use std::fmt;
+
+/// 状態空間モデルの作成とPFCの設計で発生するエラー
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// 行列やパラメータの次元が合わない
+    DimensionMismatch(&'static str),
+    /// 基底関数のグラム行列が正則でない（基底関数の個数に対して一致点が足りない等）
+    SingularGramMatrix,
+    /// 離散化周期が正の有限値でない
+    InvalidSampleTime,
+    /// 連続時間モデルが必要なところに離散時間モデルが渡された
+    NotContinuous,
+    /// 離散時間モデルが必要なところに連続時間モデルが渡された
+    NotDiscrete,
+    /// 一致点が不正
+    InvalidCoincidence(&'static str),
+    /// 設計パラメータが不正
+    InvalidParameter(&'static str),
+    /// 不安定な固有値（絶対値が1以上）のモードが出力から観測できない
+    NotDetectable,
+    /// リカッチ方程式の反復が収束しない
+    RiccatiNotConverged,
+    /// 設計の途中で逆行列を求める行列が正則でない
+    SingularMatrix(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::DimensionMismatch(msg) => write!(f, "Dimension mismatch: {}", msg),
+            Error::SingularGramMatrix => write!(f, "Gram matrix of the basis responses is singular."),
+            Error::InvalidSampleTime => write!(f, "Sample time must be positive and finite."),
+            Error::NotContinuous => write!(f, "Continuous-time model is required."),
+            Error::NotDiscrete => write!(f, "Discrete-time model is required."),
+            Error::InvalidCoincidence(msg) => write!(f, "Invalid coincidence points: {}", msg),
+            Error::InvalidParameter(msg) => write!(f, "Invalid parameter: {}", msg),
+            Error::NotDetectable => write!(f, "Unstable modes of the plant are not detectable from the outputs."),
+            Error::RiccatiNotConverged => write!(f, "Riccati equation did not converge."),
+            Error::SingularMatrix(msg) => write!(f, "Singular matrix: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,197 @@
+//! PFC(Predictive Functional Controller)の設計・解析ライブラリ
+//!
+//! 状態空間モデルの作成と離散化（StateSpace, c2d），PFCの設計と実行（designer::PFC），
+//! 制御則を差し替えるための共通インターフェース（Controller），
+//! 閉ループ系の解析・性能指標・ロバスト性評価・自動調整をまとめたもの．
+//! 組み込み向けの実行部は no_std のクレート pfc-runtime に分かれている．
+
+// 一致点と基底関数の個数によって設計時の行列サイズが変わるのでDMatrixで実装した
+
+use nalgebra::{DVector, DMatrix, RealField, SMatrix};
+use pfc_runtime::StaticStateSpace;
+
+pub mod analysis;
+pub mod basis;
+pub mod controller;
+pub mod designer;
+pub mod error;
+pub mod metrics;
+pub mod report;
+pub mod robustness;
+pub mod trajectory;
+pub mod tuner;
+#[cfg(test)]
+mod test_plants;
+
+pub use controller::{Controller, Setpoint};
+pub use error::Error;
+
+/// 状態空間モデル
+#[derive(Clone)]
+pub struct StateSpace<T> {
+    a: DMatrix<T>,  // システム行列
+    b: DMatrix<T>,  // 入力行列
+    c: DMatrix<T>,  // 出力行列
+    d: DMatrix<T>,  // 直達行列
+    sample_time: T, // 離散化周期（連続時間なら0にする）
+    delay: usize,   // 入力むだ時間[サンプル]
+}
+
+impl<T> StateSpace<T> {
+    /// 連続時間モデルなら dt = 0 とする．入力むだ時間は with_delay で設定する．
+    ///
+    ///  ------- Arguments -------
+    ///  * a: システム行列（状態数×状態数）
+    ///  * b: 入力行列（状態数×入力数）
+    ///  * c: 出力行列（出力数×状態数）
+    ///  * d: 直達行列（出力数×入力数）
+    ///  * dt: 離散化周期[s]
+    pub fn new(a: DMatrix<T>, b: DMatrix<T>, c: DMatrix<T>, d: DMatrix<T>, dt: T) -> Result<Self, Error> {
+        let check_n = (a.nrows() == a.ncols()) & (a.nrows() == b.nrows()) & (a.nrows() == c.ncols());
+        let check_l = b.ncols() == d.ncols();
+        let check_p = c.nrows() == d.nrows();
+        if check_n & check_l & check_p {
+            Ok(Self {
+                a,
+                b,
+                c,
+                d,
+                sample_time: dt,
+                delay: 0
+            })
+        } else {
+            Err(Error::DimensionMismatch("matrix sizes of the state space model are inconsistent"))
+        }
+    }
+
+    /// 入力むだ時間[サンプル]を設定する．
+    pub fn with_delay(mut self, delay: usize) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// システム行列
+    pub fn a(&self) -> &DMatrix<T> {
+        &self.a
+    }
+
+    /// 入力行列
+    pub fn b(&self) -> &DMatrix<T> {
+        &self.b
+    }
+
+    /// 出力行列
+    pub fn c(&self) -> &DMatrix<T> {
+        &self.c
+    }
+
+    /// 直達行列
+    pub fn d(&self) -> &DMatrix<T> {
+        &self.d
+    }
+
+    /// 入力むだ時間[サンプル]
+    pub fn delay(&self) -> usize {
+        self.delay
+    }
+}
+
+impl<T: Copy> StateSpace<T> {
+    /// 離散化周期[s]（連続時間なら0）
+    pub fn sample_time(&self) -> T {
+        self.sample_time
+    }
+}
+
+impl<T: RealField + Copy> StateSpace<T> {
+    /// 入力むだ時間を状態に含めた拡大系を作る．
+    ///
+    /// 状態変数は \[x; u(k-delay); ...; u(k-1)\] で，拡大系のむだ時間は0になる．
+    pub fn delay_augmented(&self) -> StateSpace<T> {
+        let n = self.a.nrows();
+        let l = self.b.ncols();
+        let p = self.c.nrows();
+        let n_a = n + l * self.delay;
+        if self.delay == 0 {
+            return self.clone();
+        }
+
+        let mut a = DMatrix::zeros(n_a, n_a);
+        a.slice_mut((0, 0), (n, n)).copy_from(&self.a);
+        a.slice_mut((0, n), (n, l)).copy_from(&self.b);
+        for i in 0..(self.delay - 1) {
+            a.slice_mut((n + l * i, n + l * (i + 1)), (l, l)).fill_with_identity();
+        }
+        let mut b = DMatrix::zeros(n_a, l);
+        b.slice_mut((n_a - l, 0), (l, l)).fill_with_identity();
+        let mut c = DMatrix::zeros(p, n_a);
+        c.slice_mut((0, 0), (p, n)).copy_from(&self.c);
+
+        StateSpace::new(a, b, c, self.d.clone(), self.sample_time).unwrap()
+    }
+
+    /// 入力むだ時間を状態に含めた静的サイズのモデルに変換する．
+    ///
+    /// N: 拡大系の状態数，L: 入力数，P: 出力数
+    pub fn to_static<const N: usize, const L: usize, const P: usize>(&self) -> Result<StaticStateSpace<T, N, L, P>, Error> {
+        let sys = self.delay_augmented();
+        if sys.a.nrows() != N || sys.b.ncols() != L || sys.c.nrows() != P {
+            return Err(Error::DimensionMismatch("sizes of the static state space model do not match"));
+        }
+        Ok(StaticStateSpace {
+            a: SMatrix::from_iterator(sys.a.iter().cloned()),
+            b: SMatrix::from_iterator(sys.b.iter().cloned()),
+            c: SMatrix::from_iterator(sys.c.iter().cloned()),
+            d: SMatrix::from_iterator(sys.d.iter().cloned()),
+            sample_time: sys.sample_time,
+        })
+    }
+
+    /// 別のスカラー型のモデルに変換する（f64で作ったモデルをf32で動かす場合など）．
+    pub fn cast<U: RealField + Copy>(&self) -> StateSpace<U> {
+        // f64を経由して変換する
+        let conv = |v: T| -> U {nalgebra::convert(nalgebra::try_convert::<T, f64>(v).unwrap())};
+        StateSpace {
+            a: self.a.map(conv),
+            b: self.b.map(conv),
+            c: self.c.map(conv),
+            d: self.d.map(conv),
+            sample_time: conv(self.sample_time),
+            delay: self.delay,
+        }
+    }
+}
+
+/// ゼロ次ホールドで離散化
+/// 
+/// 入力むだ時間[サンプル]はそのまま引き継ぐ．
+///
+/// * sys: 連続時間状態空間モデル
+/// * dt : 離散化周期[s]
+pub fn c2d<T: RealField + Copy>(sys: StateSpace<T>, dt: T) -> Result<StateSpace<T>, Error> {
+    if sys.sample_time != T::zero() {
+        return Err(Error::NotContinuous);
+    }
+    if !(dt > T::zero() && dt.is_finite()) {
+        return Err(Error::InvalidSampleTime);
+    }
+
+    let n = sys.a.ncols();
+    // 良く見る式
+    /*
+    let eye = DMatrix::identity(n, n);
+    let a_d = (sys.a.clone() * dt).exp();
+    // Ax=Bを解く --> A.lu().solve(&B)
+    let b_d = sys.a.lu().solve(&((a_d.clone() - eye) * sys.b)).unwrap();
+    */
+    // こうやってまとめれば一回で計算できる
+    let l = sys.b.ncols();
+    let mut mat = DMatrix::zeros(n + l, n + l);
+    mat.slice_mut((0, 0), (n, n)).copy_from(&(sys.a * dt));
+    mat.slice_mut((0, n), (n, l)).copy_from(&(sys.b * dt));
+    mat = mat.exp();
+    let a_d = mat.slice((0, 0), (n, n)).into();
+    let b_d = mat.slice((0, n), (n, l)).into();
+
+    Ok(StateSpace::new(a_d, b_d, sys.c, sys.d, dt)?.with_delay(sys.delay))
+}
@@ -0,0 +1,150 @@
+//! ステップ応答の性能指標
+
+use super::error::Error;
+
+/// 出力の性能指標
+#[derive(Clone, Copy, Debug)]
+pub struct StepMetrics {
+    /// 立ち上がり時間[s]（ステップの大きさの10%から90%に達するまで．達しなければ無限大）
+    pub rise_time: f64,
+    /// 整定時間[s]（最後に許容範囲を出ていた時刻の次のサンプル．整定しなければ無限大）
+    pub settling_time: f64,
+    /// オーバーシュート[%]（ステップの大きさに対する割合）
+    pub overshoot: f64,
+    /// 定常偏差（最終サンプルでの目標値 - 出力）
+    pub steady_state_error: f64,
+    /// 偏差の絶対値の積分 IAE = Σ|e| T
+    pub iae: f64,
+    /// 偏差の2乗の積分 ISE = Σe^2 T
+    pub ise: f64,
+    /// 時間で重み付けした偏差の絶対値の積分 ITAE = Σt|e| T
+    pub itae: f64,
+}
+
+/// 制御入力の性能指標
+#[derive(Clone, Copy, Debug)]
+pub struct InputMetrics {
+    /// 全変動 Σ|u(k+1) - u(k)|
+    pub total_variation: f64,
+    /// 入力制約に張り付いていたサンプルの割合
+    pub saturation_duty: f64,
+}
+
+/// 目標値に対する出力の性能指標を計算する．
+///
+/// 立ち上がり時間・整定時間・オーバーシュートは0サンプル目の出力から
+/// 目標値の最終値までのステップとして計算し，偏差の積分は各サンプルの目標値で計算する．
+///
+///  ------- Arguments -------
+///  * sample_time: サンプリング周期[s]
+///  * r: 0サンプル目からの目標値
+///  * y: 0サンプル目からの出力（rと同じ長さ）
+///  * band: 整定の許容範囲（ステップの大きさに対する割合．2%なら0.02）
+///
+/// rとyの長さが異なる場合は Error::DimensionMismatch を返す．
+pub fn step_metrics(sample_time: f64, r: &[f64], y: &[f64], band: f64) -> Result<StepMetrics, Error> {
+    if r.len() != y.len() {
+        return Err(Error::DimensionMismatch("lengths of the setpoint and the output differ"));
+    }
+    let mut iae = 0.0;
+    let mut ise = 0.0;
+    let mut itae = 0.0;
+    for (k, (r_k, y_k)) in r.iter().zip(y.iter()).enumerate() {
+        let e = r_k - y_k;
+        iae += e.abs() * sample_time;
+        ise += e * e * sample_time;
+        itae += sample_time * k as f64 * e.abs() * sample_time;
+    }
+
+    let r_f = r.last().cloned().unwrap_or(0.0);
+    let y_0 = y.first().cloned().unwrap_or(0.0);
+    let steady_state_error = r_f - y.last().cloned().unwrap_or(0.0);
+    let step = r_f - y_0;
+    if step == 0.0 {
+        return Ok(StepMetrics {rise_time: 0.0, settling_time: 0.0, overshoot: 0.0, steady_state_error, iae, ise, itae});
+    }
+
+    // ステップの大きさで正規化した出力
+    let progress = |v: f64| (v - y_0) / step;
+    let reach = |level: f64| y.iter().position(|&v| progress(v) >= level);
+    let rise_time = match (reach(0.1), reach(0.9)) {
+        (Some(k_10), Some(k_90)) => sample_time * (k_90 - k_10) as f64,
+        _ => f64::INFINITY,
+    };
+    let overshoot = y.iter().map(|&v| progress(v) - 1.0).fold(0.0, f64::max) * 100.0;
+    let settling_time = match y.iter().rposition(|&v| (v - r_f).abs() > band * step.abs() || v.is_nan()) {
+        None => 0.0,
+        Some(k) if k + 1 == y.len() => f64::INFINITY,
+        Some(k) => sample_time * (k + 1) as f64,
+    };
+    Ok(StepMetrics {rise_time, settling_time, overshoot, steady_state_error, iae, ise, itae})
+}
+
+/// 制御入力の性能指標を計算する．
+///
+///  ------- Arguments -------
+///  * u: 0サンプル目からの制御入力
+///  * limit: 制御入力制約 \[下限, 上限\]
+pub fn input_metrics(u: &[f64], limit: [f64; 2]) -> InputMetrics {
+    let total_variation = u.windows(2).map(|w| (w[1] - w[0]).abs()).sum();
+    // 丸め誤差で制約値からわずかにずれても張り付いているとみなす
+    let tol = 1e-9 * (limit[1] - limit[0]).abs().max(1.0);
+    let n_saturated = u.iter().filter(|&&v| v <= limit[0] + tol || v >= limit[1] - tol).count();
+    let saturation_duty = if u.is_empty() {0.0} else {n_saturated as f64 / u.len() as f64};
+    InputMetrics {total_variation, saturation_duty}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 差が丸め誤差の程度か
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-12
+    }
+
+    #[test]
+    fn step_metrics_match_hand_computation() {
+        // 偏差は [1, 0.5, 0.05, -0.2, -0.01, 0]
+        let y = [0.0, 0.5, 0.95, 1.2, 1.01, 1.0];
+        let m = step_metrics(0.5, &[1.0; 6], &y, 0.02).unwrap();
+        // 10%は1サンプル目，90%は2サンプル目
+        assert!(close(m.rise_time, 0.5), "{:?}", m);
+        // 最後に2%を出ているのは3サンプル目
+        assert!(close(m.settling_time, 2.0), "{:?}", m);
+        assert!(close(m.overshoot, 20.0), "{:?}", m);
+        assert!(close(m.steady_state_error, 0.0), "{:?}", m);
+        // IAE = 0.5 × 1.76，ISE = 0.5 × 1.2926，ITAE = 0.5^2 × (0.5 + 2 × 0.05 + 3 × 0.2 + 4 × 0.01)
+        assert!(close(m.iae, 0.88), "{:?}", m);
+        assert!(close(m.ise, 0.6463), "{:?}", m);
+        assert!(close(m.itae, 0.31), "{:?}", m);
+    }
+
+    #[test]
+    fn unreached_and_unsettled_responses_are_infinite() {
+        let m = step_metrics(0.1, &[1.0; 4], &[0.0, 0.2, 0.4, 0.6], 0.02).unwrap();
+        assert_eq!(m.rise_time, f64::INFINITY);
+        assert_eq!(m.settling_time, f64::INFINITY);
+        assert_eq!(m.overshoot, 0.0);
+        assert!(close(m.steady_state_error, 0.4));
+
+        // 目標値が変化しなければステップの指標は0
+        let m = step_metrics(0.1, &[0.0; 3], &[0.0, 0.1, 0.0], 0.02).unwrap();
+        assert_eq!((m.rise_time, m.settling_time, m.overshoot), (0.0, 0.0, 0.0));
+        assert!(close(m.iae, 0.01));
+    }
+
+    #[test]
+    fn mismatched_lengths_are_an_error() {
+        assert!(matches!(step_metrics(0.1, &[1.0; 3], &[0.0; 4], 0.02), Err(Error::DimensionMismatch(_))));
+    }
+
+    #[test]
+    fn input_metrics_match_hand_computation() {
+        let m = input_metrics(&[0.0, 2.0, 5.0, 5.0, -5.0, 1.0], [-5.0, 5.0]);
+        assert!(close(m.total_variation, 21.0));
+        assert!(close(m.saturation_duty, 0.5));
+        let m = input_metrics(&[], [-5.0, 5.0]);
+        assert_eq!((m.total_variation, m.saturation_duty), (0.0, 0.0));
+    }
+}
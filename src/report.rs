@@ -0,0 +1,287 @@
+//! PFCの設計結果をまとめたレポート
+
+use std::fmt;
+use nalgebra::Complex;
+
+use super::DMatrix;
+
+/// PFCの設計結果
+///
+/// 制約・オフセットフリーモード・内部モデルの安定化を考えない場合の
+/// 等価な線形制御則は
+///
+/// u = K_0 (r - y) + Nu_x x_m + Nu_r Δr
+///
+/// で，Δr は各一致点における目標値の変化分（目標値一定なら0）．
+/// 内部モデルの状態 x_m にはむだ時間分の過去の入力も含まれる．
+#[derive(Clone, Debug)]
+pub struct DesignReport {
+    /// 偏差に対するゲイン K_0（入力数×出力数）
+    pub k_0: DMatrix<f64>,
+    /// 内部モデル状態に対するゲイン Nu_x（入力数×状態数）
+    pub nu_x: DMatrix<f64>,
+    /// 一致点での目標値の変化分に対するゲイン Nu_r（入力数×一致点数）
+    pub nu_r: DMatrix<f64>,
+    /// 各一致点の (出力チャネル, むだ時間を除いたサンプル時刻)
+    pub horizons: Vec<(usize, u32)>,
+    /// 入力むだ時間[サンプル]
+    pub delay: u32,
+    /// 各一致点で参照軌道に残っている偏差の割合 φ(h)（1次遅れなら α^h）
+    pub trajectory_remaining: Vec<f64>,
+    /// 各一致点における各基底関数に対するモデル出力（一致点数×基底関数の総数）
+    pub basis_responses: DMatrix<f64>,
+    /// 最小二乗問題の条件数（列スケーリング後の基底関数応答行列）
+    ///
+    /// グラム行列の条件数はこの二乗になる．
+    pub condition_number: f64,
+    /// 公称モデルでの閉ループ極（x_m = x としたときの A + B (Nu_x - K_0 C) の固有値）
+    ///
+    /// モデル誤差 x - x_m の極（内部モデルの極）はここに含まない．
+    pub closed_loop_poles: Vec<Complex<f64>>,
+}
+
+impl DesignReport {
+    /// JSON形式の文字列に変換する．
+    pub fn to_json(&self) -> String {
+        let horizons: Vec<String> = self.horizons.iter()
+            .map(|(i, h)| format!("{{\"output\":{},\"sample\":{}}}", i, h))
+            .collect();
+        let remaining: Vec<String> = self.trajectory_remaining.iter().map(|&v| json_number(v)).collect();
+        let poles: Vec<String> = self.closed_loop_poles.iter()
+            .map(|p| format!("{{\"re\":{},\"im\":{}}}", json_number(p.re), json_number(p.im)))
+            .collect();
+        format!(
+            "{{\"k_0\":{},\"nu_x\":{},\"nu_r\":{},\"horizons\":[{}],\"delay\":{},\"trajectory_remaining\":[{}],\"basis_responses\":{},\"condition_number\":{},\"closed_loop_poles\":[{}]}}",
+            json_matrix(&self.k_0), json_matrix(&self.nu_x), json_matrix(&self.nu_r),
+            horizons.join(","), self.delay, remaining.join(","),
+            json_matrix(&self.basis_responses), json_number(self.condition_number), poles.join(",")
+        )
+    }
+}
+
+impl fmt::Display for DesignReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "===== PFC design report =====")?;
+        writeln!(f, "control law: u = K_0 (r - y) + Nu_x x_m + Nu_r dr")?;
+        write!(f, "K_0 ={}", self.k_0)?;
+        write!(f, "Nu_x ={}", self.nu_x)?;
+        write!(f, "Nu_r ={}", self.nu_r)?;
+        writeln!(f, "dead time: {} samples", self.delay)?;
+        writeln!(f, "coincidence points (output, sample, phi(h)):")?;
+        for ((i, h), phi) in self.horizons.iter().zip(self.trajectory_remaining.iter()) {
+            writeln!(f, "  ({}, {}, {:.6})", i, h, phi)?;
+        }
+        write!(f, "basis responses ={}", self.basis_responses)?;
+        writeln!(f, "condition number: {:.6e}", self.condition_number)?;
+        writeln!(f, "closed-loop poles (nominal):")?;
+        for p in self.closed_loop_poles.iter() {
+            writeln!(f, "  {:.6} {:+.6}i  (|z| = {:.6})", p.re, p.im, p.norm_sqr().sqrt())?;
+        }
+        Ok(())
+    }
+}
+
+/// 固定小数点数の実行部で使うときの量子化の評価結果
+///
+/// 各行列の誤差は要素毎の量子化誤差の絶対値の最大値．
+#[derive(Clone, Copy, Debug)]
+pub struct QuantizationReport {
+    /// 小数部のbit数
+    pub frac_bits: u32,
+    /// 偏差に対するゲイン K_0 の量子化誤差
+    pub k_0: f64,
+    /// 内部モデル状態に対するゲイン Nu_x の量子化誤差
+    pub nu_x: f64,
+    /// 目標値の変化分に対するゲイン Nu_r の量子化誤差
+    pub nu_r: f64,
+    /// 内部モデルのシステム行列 A_m の量子化誤差
+    pub a_m: f64,
+    /// 内部モデルの入力行列 B_m の量子化誤差
+    pub b_m: f64,
+    /// 量子化前の内部モデル（観測値による修正を含む）の固有値の最大絶対値
+    pub spectral_radius: f64,
+    /// 量子化後の内部モデルの固有値の最大絶対値
+    pub quantized_spectral_radius: f64,
+}
+
+impl QuantizationReport {
+    /// 量子化後も内部モデルが安定か
+    pub fn is_stable(&self) -> bool {
+        self.quantized_spectral_radius < 1.0
+    }
+}
+
+impl fmt::Display for QuantizationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "===== quantization report (Q{}) =====", self.frac_bits)?;
+        writeln!(f, "max quantization error: K_0 {:.3e}, Nu_x {:.3e}, Nu_r {:.3e}, A_m {:.3e}, B_m {:.3e}", self.k_0, self.nu_x, self.nu_r, self.a_m, self.b_m)?;
+        writeln!(f, "internal model spectral radius: {:.9} -> {:.9} ({})",
+            self.spectral_radius, self.quantized_spectral_radius, if self.is_stable() {"stable"} else {"UNSTABLE"})
+    }
+}
+
+/// JSONの数値（非有限値はnull）
+fn json_number(v: f64) -> String {
+    if v.is_finite() {
+        format!("{:e}", v)
+    } else {
+        "null".to_string()
+    }
+}
+
+/// 行毎の配列にしたJSONの行列
+fn json_matrix(m: &DMatrix<f64>) -> String {
+    let rows: Vec<String> = m.row_iter()
+        .map(|row| {
+            let v: Vec<String> = row.iter().map(|&v| json_number(v)).collect();
+            format!("[{}]", v.join(","))
+        })
+        .collect();
+    format!("[{}]", rows.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::designer::{InputChannel, OutputChannel, PFC};
+    use crate::test_plants::two_mass;
+
+    /// テストで使うJSONの値
+    #[derive(Debug, PartialEq)]
+    enum Json {
+        Null,
+        Number(f64),
+        Array(Vec<Json>),
+        Object(Vec<(String, Json)>),
+    }
+
+    impl Json {
+        fn get(&self, key: &str) -> &Json {
+            match self {
+                Json::Object(fields) => &fields.iter().find(|(k, _)| k == key).unwrap_or_else(|| panic!("no field {}", key)).1,
+                _ => panic!("not an object"),
+            }
+        }
+
+        fn array(&self) -> &[Json] {
+            match self {
+                Json::Array(v) => v,
+                _ => panic!("not an array"),
+            }
+        }
+
+        /// 行毎の配列にした行列の (行数, 列数)
+        fn shape(&self) -> (usize, usize) {
+            let rows = self.array();
+            let n_cols = rows.first().map_or(0, |row| row.array().len());
+            assert!(rows.iter().all(|row| row.array().len() == n_cols));
+            (rows.len(), n_cols)
+        }
+    }
+
+    /// 文字列・真偽値を使わない範囲のJSONを読む（書式が不正ならパニックする）
+    fn parse(text: &str) -> Json {
+        fn value(s: &[u8], i: &mut usize) -> Json {
+            match s[*i] {
+                b'{' => {
+                    *i += 1;
+                    let mut fields = Vec::new();
+                    while s[*i] != b'}' {
+                        assert_eq!(s[*i], b'"');
+                        let end = *i + 1 + s[*i + 1..].iter().position(|&c| c == b'"').unwrap();
+                        let key = String::from_utf8(s[*i + 1..end].to_vec()).unwrap();
+                        *i = end + 1;
+                        assert_eq!(s[*i], b':');
+                        *i += 1;
+                        fields.push((key, value(s, i)));
+                        if s[*i] == b',' {
+                            *i += 1;
+                        }
+                    }
+                    *i += 1;
+                    Json::Object(fields)
+                },
+                b'[' => {
+                    *i += 1;
+                    let mut items = Vec::new();
+                    while s[*i] != b']' {
+                        items.push(value(s, i));
+                        if s[*i] == b',' {
+                            *i += 1;
+                        }
+                    }
+                    *i += 1;
+                    Json::Array(items)
+                },
+                b'n' => {
+                    assert_eq!(&s[*i..*i + 4], b"null");
+                    *i += 4;
+                    Json::Null
+                },
+                _ => {
+                    let len = s[*i..].iter().position(|c| b",]}".contains(c)).unwrap();
+                    let number = std::str::from_utf8(&s[*i..*i + len]).unwrap().parse().unwrap();
+                    *i += len;
+                    Json::Number(number)
+                },
+            }
+        }
+        let s = text.as_bytes();
+        let mut i = 0;
+        let json = value(s, &mut i);
+        assert_eq!(i, s.len(), "trailing characters");
+        json
+    }
+
+    fn two_mass_report() -> DesignReport {
+        let pfc = PFC::new(&two_mass().with_delay(2), &[InputChannel::new([-50.0, 50.0]); 2], &[OutputChannel::new(1.0), OutputChannel::new(1.5)]).unwrap();
+        pfc.report().clone()
+    }
+
+    #[test]
+    fn json_has_all_fields_with_matrix_shapes() {
+        let report = two_mass_report();
+        let json = parse(&report.to_json());
+        assert_eq!(json.get("k_0").shape(), (2, 2));
+        assert_eq!(json.get("nu_x").shape(), (2, 8));
+        assert_eq!(json.get("nu_r").shape(), (2, 6));
+        assert_eq!(json.get("basis_responses").shape(), (6, 4));
+        assert_eq!(json.get("delay"), &Json::Number(2.0));
+        assert_eq!(json.get("trajectory_remaining").array().len(), 6);
+        assert_eq!(json.get("closed_loop_poles").array().len(), 8);
+        assert_eq!(json.get("condition_number"), &Json::Number(report.condition_number));
+
+        let horizon = &json.get("horizons").array()[3];
+        assert_eq!(horizon.get("output"), &Json::Number(report.horizons[3].0 as f64));
+        assert_eq!(horizon.get("sample"), &Json::Number(report.horizons[3].1 as f64));
+        let pole = &json.get("closed_loop_poles").array()[0];
+        assert_eq!(pole.get("re"), &Json::Number(report.closed_loop_poles[0].re));
+        assert_eq!(pole.get("im"), &Json::Number(report.closed_loop_poles[0].im));
+        // 数値は丸めずに書き出す
+        assert_eq!(json.get("k_0").array()[1].array()[0], Json::Number(report.k_0[(1, 0)]));
+    }
+
+    #[test]
+    fn non_finite_values_are_null() {
+        let mut report = two_mass_report();
+        report.condition_number = f64::INFINITY;
+        report.k_0[(0, 1)] = f64::NAN;
+        report.closed_loop_poles[0].im = f64::NEG_INFINITY;
+        let json = parse(&report.to_json());
+        assert_eq!(json.get("condition_number"), &Json::Null);
+        assert_eq!(json.get("k_0").array()[0].array()[1], Json::Null);
+        assert_eq!(json.get("closed_loop_poles").array()[0].get("im"), &Json::Null);
+    }
+
+    #[test]
+    fn display_lists_gains_and_coincidence_points() {
+        let report = two_mass_report();
+        let text = report.to_string();
+        assert!(text.starts_with("===== PFC design report =====\n"));
+        assert!(text.contains("dead time: 2 samples"));
+        for &(i, h) in report.horizons.iter() {
+            assert!(text.contains(&format!("  ({}, {}, ", i, h)), "({}, {})", i, h);
+        }
+        assert_eq!(text.lines().filter(|line| line.contains("|z| = ")).count(), report.closed_loop_poles.len());
+    }
+}
@@ -0,0 +1,259 @@
+//! 制御対象のパラメータ変動に対するロバスト性の評価
+
+use super::{DVector, StateSpace};
+use super::analysis;
+use super::controller::Controller;
+use super::designer::PFC;
+use super::error::Error;
+use super::metrics::{self, StepMetrics};
+
+/// パラメータ空間のサンプリング方法
+#[derive(Clone, Copy, Debug)]
+pub enum Sampling {
+    /// パラメータ毎に範囲を等分した格子点（両端を含む．1点なら範囲の中央）
+    Grid(usize),
+    /// 範囲内の一様乱数による点 (点数, 乱数のシード)
+    Random(usize, u64),
+}
+
+/// パラメータ空間の1点での評価結果
+#[derive(Clone, Debug)]
+pub struct SweepPoint {
+    /// 制御対象のパラメータ
+    pub parameters: Vec<f64>,
+    /// 閉ループ系が安定か（sweepはステップ応答が整定したか，sweep_pfcは制約なしの閉ループ極で判定）
+    pub stable: bool,
+    /// 閉ループ極の最大絶対値（sweep_pfcの場合のみ）
+    pub spectral_radius: Option<f64>,
+    /// 出力チャネル毎のステップ応答の性能指標（制約込みのシミュレーション）
+    pub metrics: Vec<StepMetrics>,
+}
+
+impl SweepPoint {
+    /// 全出力チャネルで最大のオーバーシュート[%]
+    pub fn overshoot(&self) -> f64 {
+        self.metrics.iter().map(|m| m.overshoot).fold(0.0, f64::max)
+    }
+
+    /// 全出力チャネルで最長の整定時間[s]
+    pub fn settling_time(&self) -> f64 {
+        self.metrics.iter().map(|m| m.settling_time).fold(0.0, f64::max)
+    }
+}
+
+/// ロバスト性の評価結果
+#[derive(Clone, Debug)]
+pub struct Robustness {
+    /// 各点での評価結果
+    pub points: Vec<SweepPoint>,
+}
+
+impl Robustness {
+    /// 閉ループ系が不安定になる点の数
+    pub fn n_unstable(&self) -> usize {
+        self.points.iter().filter(|p| !p.stable).count()
+    }
+
+    /// 安定な点のうちオーバーシュートが最大の点
+    pub fn worst_overshoot(&self) -> Option<&SweepPoint> {
+        self.points.iter()
+            .filter(|p| p.stable)
+            .max_by(|a, b| a.overshoot().total_cmp(&b.overshoot()))
+    }
+
+    /// 安定な点のうち整定時間が最長の点
+    pub fn worst_settling_time(&self) -> Option<&SweepPoint> {
+        self.points.iter()
+            .filter(|p| p.stable)
+            .max_by(|a, b| a.settling_time().total_cmp(&b.settling_time()))
+    }
+
+    /// 不安定になる点を含むパラメータ毎の範囲 \[最小, 最大\]（不安定な点がなければNone）
+    pub fn unstable_region(&self) -> Option<Vec<[f64; 2]>> {
+        let mut unstable = self.points.iter().filter(|p| !p.stable);
+        let first = unstable.next()?;
+        let mut region: Vec<[f64; 2]> = first.parameters.iter().map(|&v| [v, v]).collect();
+        for point in unstable {
+            for (range, &v) in region.iter_mut().zip(point.parameters.iter()) {
+                range[0] = range[0].min(v);
+                range[1] = range[1].max(v);
+            }
+        }
+        Some(region)
+    }
+}
+
+/// 公称モデルで設計した制御器を，パラメータを変えた制御対象で評価する．
+///
+/// 各点で制約込みのステップ応答をシミュレーションし，全出力チャネルが整定すれば安定とみなす．
+/// 制御器は Controller トレイト経由で動かすので，PFC以外の制御器も評価できる．
+/// 制御器は点毎に複製して使うので，内部状態は渡した時点のものから始まる（設計直後のものを渡すこと）．
+///
+///  ------- Arguments -------
+///  * controller: 公称モデルで設計した制御器
+///  * plant: パラメータから離散時間状態空間モデルを作る関数
+///  * ranges: パラメータ毎の範囲 \[最小, 最大\]
+///  * sampling: パラメータ空間のサンプリング方法
+///  * r: ステップ状の目標値
+///  * n_steps: シミュレーションするサンプル数
+///  * band: 整定の許容範囲（ステップの大きさに対する割合）
+pub fn sweep<C, F>(controller: &C, plant: F, ranges: &[[f64; 2]], sampling: Sampling, r: &DVector<f64>, n_steps: usize, band: f64) -> Result<Robustness, Error>
+where
+    C: Controller + Clone,
+    F: Fn(&[f64]) -> Result<StateSpace<f64>, Error>,
+{
+    if ranges.iter().any(|range| range[0] > range[1] || range.iter().any(|v| v.is_nan())) {
+        return Err(Error::InvalidParameter("lower bound of a parameter range exceeds the upper bound"));
+    }
+
+    let mut points = Vec::new();
+    for parameters in sample_points(ranges, sampling)? {
+        let sys = plant(&parameters)?;
+        let (y, _) = analysis::simulate_step(controller, &sys, r, n_steps);
+        let metrics: Vec<StepMetrics> = (0..r.len())
+            .map(|i| {
+                let y_i: Vec<f64> = y.iter().map(|y| y[i]).collect();
+                metrics::step_metrics(sys.sample_time, &vec![r[i]; y_i.len()], &y_i, band)
+            })
+            .collect::<Result<_, _>>()?;
+        let stable = metrics.iter().all(|m| m.settling_time.is_finite());
+
+        points.push(SweepPoint {parameters, stable, spectral_radius: None, metrics});
+    }
+    Ok(Robustness {points})
+}
+
+/// sweep に加えて，各点で制約なしのPFCと制御対象の閉ループ極を求める．
+///
+/// 安定性はシミュレーションではなく閉ループ極で判定する（引数は sweep と同じ）．
+pub fn sweep_pfc<F>(pfc: &PFC, plant: F, ranges: &[[f64; 2]], sampling: Sampling, r: &DVector<f64>, n_steps: usize, band: f64) -> Result<Robustness, Error>
+where
+    F: Fn(&[f64]) -> Result<StateSpace<f64>, Error>,
+{
+    let mut robustness = sweep(pfc, &plant, ranges, sampling, r, n_steps, band)?;
+    for point in robustness.points.iter_mut() {
+        let closed_loop = analysis::closed_loop(&plant(&point.parameters)?, pfc)?;
+        point.stable = closed_loop.is_stable();
+        point.spectral_radius = Some(closed_loop.poles.iter().map(|p| p.norm_sqr().sqrt()).fold(0.0, f64::max));
+    }
+    Ok(robustness)
+}
+
+/// パラメータ空間の点の列を作る．
+fn sample_points(ranges: &[[f64; 2]], sampling: Sampling) -> Result<Vec<Vec<f64>>, Error> {
+    match sampling {
+        Sampling::Grid(n) => {
+            if n == 0 {
+                return Err(Error::InvalidParameter("number of grid points must be positive"));
+            }
+            let value = |range: &[f64; 2], k: usize| if n == 1 {
+                0.5 * (range[0] + range[1])
+            } else {
+                range[0] + (range[1] - range[0]) * k as f64 / (n - 1) as f64
+            };
+            // 格子点の番号をn進数として数え上げる
+            let n_points = n.pow(ranges.len() as u32);
+            Ok((0..n_points).map(|mut index| {
+                ranges.iter().map(|range| {
+                    let k = index % n;
+                    index /= n;
+                    value(range, k)
+                }).collect()
+            }).collect())
+        },
+        Sampling::Random(n, seed) => {
+            let mut rng = XorShift::new(seed);
+            Ok((0..n).map(|_| {
+                ranges.iter().map(|range| range[0] + (range[1] - range[0]) * rng.next_f64()).collect()
+            }).collect())
+        },
+    }
+}
+
+/// 外部クレートを使わないための簡単な擬似乱数生成器（xorshift64*）
+struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        // 状態が0だと0しか出ないので避ける
+        Self {state: if seed == 0 {0x9E37_79B9_7F4A_7C15} else {seed}}
+    }
+
+    /// \[0, 1) の一様乱数
+    fn next_f64(&mut self) -> f64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        let v = self.state.wrapping_mul(0x2545_F491_4F6C_DD1D);
+        (v >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DMatrix;
+    use crate::designer::{Coincidence, InputChannel, OutputChannel};
+    use crate::test_plants::{mass_spring_damper, Integral};
+
+    /// 積分制御器との閉ループ系 \[A - g B C, B; -g C, 1\] の極の最大絶対値
+    fn integral_spectral_radius(sys: &StateSpace<f64>, gain: f64) -> f64 {
+        let n = sys.a().nrows();
+        let mut a = DMatrix::zeros(n + 1, n + 1);
+        a.slice_mut((0, 0), (n, n)).copy_from(&(sys.a() - sys.b() * sys.c() * gain));
+        a.slice_mut((0, n), (n, 1)).copy_from(sys.b());
+        a.slice_mut((n, 0), (1, n)).copy_from(&(sys.c() * -gain));
+        a[(n, n)] = 1.0;
+        a.complex_eigenvalues().iter().map(|p| p.norm_sqr().sqrt()).fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn sweep_runs_any_controller_through_the_trait() {
+        let gain = 0.1;
+        let plant = |p: &[f64]| Ok(mass_spring_damper(5.0, 5.0, p[0]));
+        let r = DVector::from_element(1, 0.1);
+        let robustness = sweep(&Integral::new(gain, [-100.0, 100.0]), plant, &[[1.0, 10.0]], Sampling::Grid(4), &r, 600, 0.02).unwrap();
+
+        assert_eq!(robustness.points.len(), 4);
+        for point in robustness.points.iter() {
+            assert!(point.spectral_radius.is_none());
+            let stable = integral_spectral_radius(&plant(&point.parameters).unwrap(), gain) < 1.0;
+            assert_eq!(point.stable, stable, "k = {}", point.parameters[0]);
+        }
+        assert!(robustness.n_unstable() > 0);
+        assert_eq!(robustness.unstable_region().unwrap(), vec![[1.0, 1.0]]);
+    }
+
+    #[test]
+    fn sweep_pfc_judges_stability_by_closed_loop_poles() {
+        // 公称モデル（m = 5, むだ時間なし）で設計したPFCを，質量とむだ時間がずれた制御対象で評価する
+        let pfc = PFC::new(
+            &mass_spring_damper(5.0, 5.0, 5.0),
+            &[InputChannel {n_b: 2, ..InputChannel::new([-50.0, 50.0])}],
+            &[OutputChannel {coincidence: Coincidence::Uniform(2), ..OutputChannel::new(0.3)}]
+        ).unwrap();
+        let plant = |p: &[f64]| Ok(mass_spring_damper(p[0], 5.0, 5.0).with_delay(p[1].round() as usize));
+        let r = DVector::from_element(1, 0.1);
+        let robustness = sweep_pfc(&pfc, plant, &[[2.5, 7.5], [0.0, 4.0]], Sampling::Grid(5), &r, 400, 0.02).unwrap();
+
+        assert_eq!(robustness.points.len(), 25);
+        for point in robustness.points.iter() {
+            let spectral_radius = point.spectral_radius.unwrap();
+            assert_eq!(point.stable, spectral_radius < 1.0, "{:?}", point.parameters);
+            // 極による判定は制約込みのシミュレーションで整定するかとも一致する
+            assert_eq!(point.stable, point.settling_time().is_finite(), "{:?}", point.parameters);
+        }
+
+        // 質量が軽く，むだ時間が長いほど不安定になる
+        let unstable: Vec<&[f64]> = robustness.points.iter()
+            .filter(|p| !p.stable)
+            .map(|p| p.parameters.as_slice())
+            .collect();
+        assert_eq!(unstable, vec![&[2.5, 2.0][..], &[2.5, 3.0], &[3.75, 3.0], &[2.5, 4.0], &[3.75, 4.0], &[5.0, 4.0]]);
+        assert_eq!(robustness.unstable_region().unwrap(), vec![[2.5, 5.0], [2.0, 4.0]]);
+        let worst = robustness.worst_settling_time().unwrap();
+        assert!(worst.stable && worst.settling_time().is_finite());
+    }
+}
@@ -0,0 +1,107 @@
+//! テストで使う制御対象と制御器
+
+use super::{c2d, DMatrix, DVector, StateSpace};
+use super::controller::{Controller, Setpoint};
+
+/// テストで使う離散化周期[s]
+pub const SAMPLE_TIME: f64 = 0.05;
+
+/// バネ・マス・ダンパ系を離散化したモデル（状態は位置と速度，入力は力，出力は位置）
+///
+/// * m: 質量[kg]
+/// * c: 減衰係数[Ns/m]
+/// * k: バネ定数[N/m]
+pub fn mass_spring_damper(m: f64, c: f64, k: f64) -> StateSpace<f64> {
+    let a = DMatrix::from_row_slice(2, 2, &[
+        0.0, 1.0,
+        -k / m, -c / m,
+    ]);
+    let b = DMatrix::from_row_slice(2, 1, &[0.0, 1.0 / m]);
+    let c = DMatrix::from_row_slice(1, 2, &[1.0, 0.0]);
+    let d = DMatrix::zeros(1, 1);
+    c2d(StateSpace::new(a, b, c, d, 0.0).unwrap(), SAMPLE_TIME).unwrap()
+}
+
+/// 壁と結合バネでつながった2質点系を離散化したモデル
+///
+/// 状態は \[x_1, v_1, x_2, v_2\]，入力は各質点に加える力，出力は各質点の位置．
+/// 質量はどちらも1 \[kg\]，壁とのバネ定数は2 \[N/m\]，結合バネは1 \[N/m\]，減衰係数は0.5 \[Ns/m\]．
+pub fn two_mass() -> StateSpace<f64> {
+    let (k, k_c, c) = (2.0, 1.0, 0.5);
+    let a = DMatrix::from_row_slice(4, 4, &[
+        0.0, 1.0, 0.0, 0.0,
+        -(k + k_c), -c, k_c, 0.0,
+        0.0, 0.0, 0.0, 1.0,
+        k_c, 0.0, -(k + k_c), -c,
+    ]);
+    let b = DMatrix::from_row_slice(4, 2, &[
+        0.0, 0.0,
+        1.0, 0.0,
+        0.0, 0.0,
+        0.0, 1.0,
+    ]);
+    let c = DMatrix::from_row_slice(2, 4, &[
+        1.0, 0.0, 0.0, 0.0,
+        0.0, 0.0, 1.0, 0.0,
+    ]);
+    let d = DMatrix::zeros(2, 2);
+    c2d(StateSpace::new(a, b, c, d, 0.0).unwrap(), SAMPLE_TIME).unwrap()
+}
+
+/// 制御対象とコントローラの閉ループ系をシミュレーションする．
+///
+/// * plant: 離散時間状態空間モデル（むだ時間はない前提）
+/// * controller: コントローラ（初期状態から始める）
+/// * r: 各サンプルの目標値（長さがサンプル数）
+/// * preview: 将来の目標値を先読みさせるか
+/// * disturbance: k サンプル目に入力に加わる外乱
+///
+/// 各サンプルの (出力, 制御入力) を返す．
+pub fn simulate<C: Controller>(plant: &StateSpace<f64>, controller: &mut C, r: &[DVector<f64>], preview: bool, disturbance: impl Fn(usize) -> DVector<f64>) -> Vec<(DVector<f64>, DVector<f64>)> {
+    let mut x = DVector::zeros(plant.a().nrows());
+    let mut log = Vec::with_capacity(r.len());
+    for k in 0..r.len() {
+        let y = plant.c() * &x;
+        let setpoint = if preview {Setpoint::Sequence(&r[k..])} else {Setpoint::Constant(&r[k])};
+        let u = controller.update(&setpoint, &y);
+        x = plant.a() * &x + plant.b() * (&u + disturbance(k));
+        log.push((y, u));
+    }
+    log
+}
+
+/// 積分制御器 u(k) = u(k-1) + gain (r(k) - y(k))（PFC以外の制御器の例，1入力1出力）
+#[derive(Clone)]
+pub struct Integral {
+    gain: f64,              // 積分ゲイン
+    limit: [[f64; 2]; 1],   // 制御入力制約 [下限, 上限]
+    u: DVector<f64>,        // 前回の制御入力
+}
+
+impl Integral {
+    pub fn new(gain: f64, limit: [f64; 2]) -> Self {
+        Self {gain, limit: [limit], u: DVector::zeros(1)}
+    }
+}
+
+impl Controller for Integral {
+    fn reset(&mut self) {
+        self.u.fill(0.0);
+    }
+
+    fn update(&mut self, setpoint: &Setpoint, y: &DVector<f64>) -> DVector<f64> {
+        if !setpoint.is_empty() {
+            let u = self.u[0] + self.gain * (setpoint.at(0, 0) - y[0]);
+            self.u[0] = u.clamp(self.limit[0][0], self.limit[0][1]);
+        }
+        self.u.clone()
+    }
+
+    fn input_limits(&self) -> &[[f64; 2]] {
+        &self.limit
+    }
+
+    fn state(&self) -> DVector<f64> {
+        self.u.clone()
+    }
+}
@@ -0,0 +1,87 @@
+//! PFCの参照軌道
+
+/// 臨界減衰2次系の応答が95%に達する時刻 x = ω t の解（(1 + x) e^(-x) = 0.05）
+const SECOND_ORDER_X95: f64 = 4.743864518;
+
+/// 参照軌道の形状
+///
+/// 現在の偏差に対して，tだけ先の時刻に残っている偏差の割合 φ(t) で表す（φ(0) = 1）．
+/// どの形状も閉ループ応答時間t_clrtで偏差の95%が解消するように決める．
+#[derive(Clone, Copy, Debug)]
+pub enum ReferenceTrajectory {
+    /// 1次遅れ φ(t) = exp(-3 t / t_clrt)
+    FirstOrder,
+    /// 臨界減衰の2次遅れ φ(t) = (1 + ω t) exp(-ω t)
+    SecondOrder,
+    /// 任意の形状 φ(t) = f(t, t_clrt)
+    Custom(fn(f64, f64) -> f64),
+}
+
+impl ReferenceTrajectory {
+    /// tだけ先の時刻に残っている偏差の割合を計算する．
+    ///
+    /// PFC::new は一致点での値が \[0, 1\] の範囲外（非有限値を含む）ならエラーを返す．
+    ///
+    ///  ------- Arguments -------
+    ///  * t: 現在時刻からの経過時間
+    ///  * t_clrt: 閉ループ応答時間
+    pub fn remaining(&self, t: f64, t_clrt: f64) -> f64 {
+        match *self {
+            ReferenceTrajectory::FirstOrder => (-3.0 * t / t_clrt).exp(),
+            ReferenceTrajectory::SecondOrder => {
+                let omega_t = SECOND_ORDER_X95 * t / t_clrt;
+                (1.0 + omega_t) * (-omega_t).exp()
+            },
+            ReferenceTrajectory::Custom(f) => f(t, t_clrt),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DVector;
+    use crate::designer::{InputChannel, OutputChannel, PFC};
+    use crate::error::Error;
+    use crate::test_plants::{mass_spring_damper, simulate};
+
+    /// 参照軌道を指定したPFCを設計する
+    fn design(trajectory: ReferenceTrajectory) -> Result<PFC, Error> {
+        let plant = mass_spring_damper(5.0, 5.0, 5.0);
+        PFC::new(&plant, &[InputChannel::new([-50.0, 50.0])], &[OutputChannel {trajectory, ..OutputChannel::new(1.0)}])
+    }
+
+    #[test]
+    fn deviation_is_reduced_by_95_percent_at_response_time() {
+        for trajectory in [ReferenceTrajectory::FirstOrder, ReferenceTrajectory::SecondOrder] {
+            assert_eq!(trajectory.remaining(0.0, 2.0), 1.0);
+            assert!((trajectory.remaining(2.0, 2.0) - 0.05).abs() < 2e-3, "{:?}", trajectory);
+        }
+        assert!((ReferenceTrajectory::SecondOrder.remaining(2.0, 2.0) - 0.05).abs() < 1e-9);
+        // 臨界減衰なので単調に減少する
+        let phi: Vec<f64> = (0..=40).map(|k| ReferenceTrajectory::SecondOrder.remaining(0.05 * k as f64, 1.0)).collect();
+        assert!(phi.windows(2).all(|w| w[1] < w[0]), "{:?}", phi);
+    }
+
+    #[test]
+    fn second_order_design_tracks_step() {
+        let plant = mass_spring_damper(5.0, 5.0, 5.0);
+        let mut pfc = design(ReferenceTrajectory::SecondOrder).unwrap();
+        let r = vec![DVector::from_element(1, 0.1); 400];
+        let log = simulate(&plant, &mut pfc, &r, false, |_| DVector::zeros(1));
+        let y = log.last().unwrap().0[0];
+        assert!((y - 0.1).abs() < 1e-6, "y = {}", y);
+        // 1次遅れより立ち上がりが緩やかなので初期の入力が小さい
+        let mut first_order = design(ReferenceTrajectory::FirstOrder).unwrap();
+        let u_first = first_order.update(&r[0], &DVector::zeros(1))[0];
+        assert!(log[0].1[0] < u_first, "{} >= {}", log[0].1[0], u_first);
+    }
+
+    #[test]
+    fn custom_trajectory_out_of_range_is_rejected() {
+        for f in [|_, _| f64::NAN, |_, _| -0.1, |_, _| 1.5] {
+            assert!(matches!(design(ReferenceTrajectory::Custom(f)), Err(Error::InvalidParameter(_))));
+        }
+        assert!(design(ReferenceTrajectory::Custom(|t, t_clrt| (1.0 - t / t_clrt).max(0.0))).is_ok());
+    }
+}
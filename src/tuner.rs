@@ -0,0 +1,262 @@
+//! 制御器のパラメータの自動調整（PFCでは閉ループ応答時間・基底関数の個数・一致点の個数）
+
+use super::{DVector, StateSpace};
+use super::analysis;
+use super::controller::Controller;
+use super::designer::PFC;
+use super::error::Error;
+use super::metrics;
+
+/// tune_pfcで調整するPFCのパラメータの組（全チャネル共通）
+#[derive(Clone, Copy, Debug)]
+pub struct Candidate {
+    pub t_clrt: f64,  // 閉ループ応答時間[s]
+    pub n_b: usize,   // 基底関数の個数
+    pub n_h: usize,   // 一致点の個数
+}
+
+/// tune_pfcで探索するPFCのパラメータの候補
+#[derive(Clone, Debug)]
+pub struct SearchSpace {
+    pub t_clrt: Vec<f64>,
+    pub n_b: Vec<usize>,
+    pub n_h: Vec<usize>,
+}
+
+impl SearchSpace {
+    /// 候補の全ての組み合わせ
+    pub fn candidates(&self) -> Vec<Candidate> {
+        let mut candidates = Vec::with_capacity(self.t_clrt.len() * self.n_b.len() * self.n_h.len());
+        for &t_clrt in self.t_clrt.iter() {
+            for &n_b in self.n_b.iter() {
+                for &n_h in self.n_h.iter() {
+                    candidates.push(Candidate {t_clrt, n_b, n_h});
+                }
+            }
+        }
+        candidates
+    }
+}
+
+/// 評価関数の重み
+///
+/// 評価関数は各指標の重み付き和で，小さいほど良い．
+#[derive(Clone, Copy, Debug)]
+pub struct Objective {
+    pub settling_time: f64,     // 整定時間[s]
+    pub overshoot: f64,         // オーバーシュート[%]
+    pub input_energy: f64,      // 入力のエネルギー Σ|u|^2 T
+    pub peak_sensitivity: f64,  // 感度関数のピーク値（ロバスト性，tune_pfcのみ）
+}
+
+/// 候補の評価結果
+#[derive(Clone, Copy, Debug)]
+pub struct Evaluation<P = Candidate> {
+    pub candidate: P,           // 制御器のパラメータ
+    pub settling_time: f64,     // 全出力チャネルで最長の整定時間[s]
+    pub overshoot: f64,         // 全出力チャネルで最大のオーバーシュート[%]
+    pub input_energy: f64,      // 入力のエネルギー Σ|u|^2 T
+    pub peak_sensitivity: Option<f64>,  // 出力で見た感度関数の最大特異値のピーク値（tune_pfcのみ）
+    pub cost: f64,              // 評価関数の値
+}
+
+/// 自動調整の結果
+#[derive(Clone, Debug)]
+pub struct Tuning<P = Candidate> {
+    /// 評価関数が最小の候補
+    pub best: Evaluation<P>,
+    /// 設計でき，閉ループ系が安定だった全ての候補
+    pub evaluations: Vec<Evaluation<P>>,
+}
+
+/// 候補の閉ループ系の判定
+enum Verdict {
+    /// 線形解析で不安定
+    Unstable,
+    /// 線形解析で安定（感度関数のピーク値）
+    Stable(f64),
+    /// 線形解析をしない（シミュレーションで判定する）
+    Unknown,
+}
+
+/// パラメータの候補毎に制御器を設計し，ステップ応答のシミュレーションで評価する．
+///
+/// 制御器は Controller トレイト経由で動かすので，PFC以外の制御器も調整できる．
+/// 候補はどんな型でもよく（PIDなら (K_p, K_i, K_d) など），設計できない候補と
+/// ステップ応答が整定しない候補は除外する．
+/// 感度関数のピーク値は線形解析が必要なので，重みを0以外にする場合は tune_pfc を使う．
+///
+///  ------- Arguments -------
+///  * sys: 離散時間状態空間モデル（むだ時間を含んでよい）
+///  * candidates: 制御器のパラメータの候補
+///  * design: 候補から制御器を設計する関数（制約などの設定もここで行う）
+///  * objective: 評価関数の重み
+///  * r: ステップ状の目標値
+///  * n_steps: シミュレーションするサンプル数
+///  * band: 整定の許容範囲（ステップの大きさに対する割合）
+pub fn tune<P, C, F>(sys: &StateSpace<f64>, candidates: impl IntoIterator<Item = P>, design: F, objective: &Objective, r: &DVector<f64>, n_steps: usize, band: f64) -> Result<Tuning<P>, Error>
+where
+    P: Clone,
+    C: Controller + Clone,
+    F: Fn(&P) -> Result<C, Error>,
+{
+    if objective.peak_sensitivity != 0.0 {
+        return Err(Error::InvalidParameter("peak sensitivity can be weighted only with tune_pfc"));
+    }
+    search(sys, candidates, design, |_| Ok(Verdict::Unknown), objective, r, n_steps, band)
+}
+
+/// 閉ループ応答時間・基底関数の個数・一致点の個数の全ての組み合わせについてPFCを設計し，評価する．
+///
+/// tune に加えて制約なしのPFCと制御対象の閉ループ系を解析し，
+/// 閉ループ系が不安定になる組み合わせを除外して評価関数に感度関数のピーク値を含める．
+/// 設計できない組み合わせ（一致点より基底関数が多い場合など）も除外する．
+///
+///  ------- Arguments -------
+///  * sys: 離散時間状態空間モデル（むだ時間を含んでよい）
+///  * design: 候補からPFCを設計する関数（制約などの設定もここで行う）
+///  * space: 探索するパラメータの候補
+///  * objective: 評価関数の重み
+///  * r: ステップ状の目標値
+///  * n_steps: シミュレーションするサンプル数
+///  * band: 整定の許容範囲（ステップの大きさに対する割合）
+pub fn tune_pfc<F>(sys: &StateSpace<f64>, design: F, space: &SearchSpace, objective: &Objective, r: &DVector<f64>, n_steps: usize, band: f64) -> Result<Tuning, Error>
+where
+    F: Fn(&Candidate) -> Result<PFC, Error>,
+{
+    let analyze = |pfc: &PFC| -> Result<Verdict, Error> {
+        let closed_loop = analysis::closed_loop(sys, pfc)?;
+        Ok(if closed_loop.is_stable() {
+            Verdict::Stable(analysis::peak_gain(&closed_loop.sensitivity))
+        } else {
+            Verdict::Unstable
+        })
+    };
+    search(sys, space.candidates(), design, analyze, objective, r, n_steps, band)
+}
+
+/// 候補毎に設計・判定・評価する．
+#[allow(clippy::too_many_arguments)]
+fn search<P, C, F, A>(sys: &StateSpace<f64>, candidates: impl IntoIterator<Item = P>, design: F, analyze: A, objective: &Objective, r: &DVector<f64>, n_steps: usize, band: f64) -> Result<Tuning<P>, Error>
+where
+    P: Clone,
+    C: Controller + Clone,
+    F: Fn(&P) -> Result<C, Error>,
+    A: Fn(&C) -> Result<Verdict, Error>,
+{
+    let mut evaluations = Vec::new();
+    for candidate in candidates {
+        let controller = match design(&candidate) {
+            Ok(controller) => controller,
+            Err(_) => continue,
+        };
+        let peak_sensitivity = match analyze(&controller)? {
+            Verdict::Unstable => continue,
+            Verdict::Stable(peak) => Some(peak),
+            Verdict::Unknown => None,
+        };
+        let evaluation = evaluate(candidate, &controller, sys, peak_sensitivity, objective, r, n_steps, band)?;
+        if peak_sensitivity.is_none() && !evaluation.settling_time.is_finite() {
+            continue;
+        }
+        evaluations.push(evaluation);
+    }
+
+    let best = evaluations.iter()
+        .min_by(|a, b| a.cost.total_cmp(&b.cost))
+        .cloned()
+        .ok_or(Error::InvalidParameter("no candidate gives a stable closed loop"))?;
+    Ok(Tuning {best, evaluations})
+}
+
+/// 1つの候補を評価する．
+#[allow(clippy::too_many_arguments)]
+fn evaluate<P, C: Controller + Clone>(candidate: P, controller: &C, sys: &StateSpace<f64>, peak_sensitivity: Option<f64>, objective: &Objective, r: &DVector<f64>, n_steps: usize, band: f64) -> Result<Evaluation<P>, Error> {
+    let (y, u) = analysis::simulate_step(controller, sys, r, n_steps);
+    let mut settling_time: f64 = 0.0;
+    let mut overshoot: f64 = 0.0;
+    for i in 0..r.len() {
+        let y_i: Vec<f64> = y.iter().map(|y| y[i]).collect();
+        let m = metrics::step_metrics(sys.sample_time, &vec![r[i]; y_i.len()], &y_i, band)?;
+        settling_time = settling_time.max(m.settling_time);
+        overshoot = overshoot.max(m.overshoot);
+    }
+    let input_energy = u.iter().map(|u| u.norm_squared()).sum::<f64>() * sys.sample_time;
+
+    // 重みが0の指標は無限大でも評価関数に含めない
+    let weighted = |w: f64, v: f64| if w == 0.0 {0.0} else {w * v};
+    let cost = weighted(objective.settling_time, settling_time)
+        + weighted(objective.overshoot, overshoot)
+        + weighted(objective.input_energy, input_energy)
+        + weighted(objective.peak_sensitivity, peak_sensitivity.unwrap_or(0.0));
+    Ok(Evaluation {candidate, settling_time, overshoot, input_energy, peak_sensitivity, cost})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::designer::{Coincidence, InputChannel, OutputChannel};
+    use crate::test_plants::{mass_spring_damper, Integral};
+
+    #[test]
+    fn tune_runs_any_controller_through_the_trait() {
+        let sys = mass_spring_damper(5.0, 5.0, 5.0);
+        // 候補は積分ゲインそのもの（負のゲインは設計できないものとする）
+        let design = |&gain: &f64| if gain > 0.0 {
+            Ok(Integral::new(gain, [-100.0, 100.0]))
+        } else {
+            Err(Error::InvalidParameter("integral gain must be positive"))
+        };
+        let objective = Objective {settling_time: 1.0, overshoot: 0.0, input_energy: 0.0, peak_sensitivity: 0.0};
+        let r = DVector::from_element(1, 0.1);
+        let tuning = tune(&sys, [-0.1, 1.0, 0.1, 0.05], design, &objective, &r, 600, 0.02).unwrap();
+
+        // ゲイン1は発散するので除外される
+        let gains: Vec<f64> = tuning.evaluations.iter().map(|e| e.candidate).collect();
+        assert_eq!(gains, vec![0.1, 0.05]);
+        assert!(tuning.evaluations.iter().all(|e| e.settling_time.is_finite() && e.peak_sensitivity.is_none()));
+        assert!(tuning.best.settling_time <= tuning.evaluations.iter().map(|e| e.settling_time).fold(f64::INFINITY, f64::min));
+
+        let robust = Objective {peak_sensitivity: 1.0, ..objective};
+        assert!(matches!(tune(&sys, [0.1], design, &robust, &r, 600, 0.02), Err(Error::InvalidParameter(_))));
+    }
+
+    #[test]
+    fn tune_pfc_excludes_unstable_and_undesignable_candidates() {
+        // むだ時間を無視してPFCを設計するので，速すぎる応答は不安定になる
+        let model = mass_spring_damper(5.0, 5.0, 5.0);
+        let sys = model.clone().with_delay(4);
+        let design = |c: &Candidate| PFC::new(
+            &model,
+            &[InputChannel {n_b: c.n_b, ..InputChannel::new([-50.0, 50.0])}],
+            &[OutputChannel {coincidence: Coincidence::Uniform(c.n_h), ..OutputChannel::new(c.t_clrt)}]
+        );
+        let space = SearchSpace {t_clrt: vec![0.1, 0.5, 1.0], n_b: vec![1, 2], n_h: vec![1, 2]};
+        let objective = Objective {settling_time: 1.0, overshoot: 0.05, input_energy: 0.0, peak_sensitivity: 0.5};
+        let r = DVector::from_element(1, 0.1);
+        let tuning = tune_pfc(&sys, design, &space, &objective, &r, 200, 0.02).unwrap();
+
+        // t_clrt = 0.1 は不安定，n_b = 2, n_h = 1 は設計できない
+        let kept: Vec<(f64, usize, usize)> = tuning.evaluations.iter()
+            .map(|e| (e.candidate.t_clrt, e.candidate.n_b, e.candidate.n_h))
+            .collect();
+        assert_eq!(kept, vec![(0.5, 1, 1), (0.5, 1, 2), (0.5, 2, 2), (1.0, 1, 1), (1.0, 1, 2), (1.0, 2, 2)]);
+
+        for e in tuning.evaluations.iter() {
+            let closed_loop = analysis::closed_loop(&sys, &design(&e.candidate).unwrap()).unwrap();
+            assert!(closed_loop.is_stable());
+            let peak = analysis::peak_gain(&closed_loop.sensitivity);
+            assert_eq!(e.peak_sensitivity, Some(peak));
+            let cost = e.settling_time + 0.05 * e.overshoot + 0.5 * peak;
+            assert!((e.cost - cost).abs() < 1e-12 * cost, "{} vs {}", e.cost, cost);
+            assert!(tuning.best.cost <= e.cost);
+        }
+
+        // 感度関数のピーク値だけに重みを付けると，ピーク値が最小の候補が選ばれる
+        let robust = Objective {settling_time: 0.0, overshoot: 0.0, input_energy: 0.0, peak_sensitivity: 1.0};
+        let tuning = tune_pfc(&sys, design, &space, &robust, &r, 200, 0.02).unwrap();
+        let min_peak = tuning.evaluations.iter().filter_map(|e| e.peak_sensitivity).fold(f64::INFINITY, f64::min);
+        assert_eq!(tuning.best.peak_sensitivity, Some(min_peak));
+        assert_eq!((tuning.best.candidate.t_clrt, tuning.best.candidate.n_b, tuning.best.candidate.n_h), (1.0, 1, 1));
+    }
+}
@@ -0,0 +1,76 @@
+//! PFC::update_intoが動的確保をしないことの確認
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use nalgebra::{DMatrix, DVector};
+use pfc_dynamic::designer::{InputChannel, OutputChannel, PFC};
+use pfc_dynamic::{c2d, Setpoint, StateSpace};
+
+/// 動的確保の回数を数えるアロケータ
+///
+/// テストハーネスの他のスレッドの確保を数えないようにスレッド毎に数える．
+struct CountingAllocator;
+
+thread_local! {
+    static N_ALLOC: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = N_ALLOC.try_with(|n| n.set(n.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// このスレッドでこれまでに動的確保した回数
+fn n_alloc() -> usize {
+    N_ALLOC.with(|n| n.get())
+}
+
+#[test]
+fn update_into_does_not_allocate() {
+    // バネ・マス・ダンパ系（m = 5, c = 5, k = 5）に3サンプルのむだ時間
+    let plant = c2d(StateSpace::new(
+        DMatrix::from_row_slice(2, 2, &[0.0, 1.0, -1.0, -1.0]),
+        DMatrix::from_row_slice(2, 1, &[0.0, 0.2]),
+        DMatrix::from_row_slice(1, 2, &[1.0, 0.0]),
+        DMatrix::zeros(1, 1),
+        0.0,
+    ).unwrap(), 0.05).unwrap().with_delay(3);
+    let mut pfc = PFC::new(&plant, &[InputChannel::new([-5.0, 5.0])], &[OutputChannel::new(0.5)]).unwrap();
+    pfc.set_rate_limit(Some(&[[-2.0, 2.0]])).unwrap();
+    pfc.set_output_limit(Some(&[[-1.0, 0.105]])).unwrap();
+    pfc.set_state_limit(Some(&[(1, [-0.15, 0.15])])).unwrap();
+    pfc.enable_offset_free(1e-6, 1e-2, 1e-6).unwrap();
+
+    // 先読みする目標値（0.1 [m]と0 [m]の往復）と制御対象のシミュレーションに使う領域は先に確保する
+    let plant = plant.delay_augmented();
+    let profile: Vec<DVector<f64>> = (0..400).map(|k| DVector::from_element(1, if k < 200 {0.1} else {0.0})).collect();
+    let mut x = DVector::zeros(plant.a().nrows());
+    let mut x_next = x.clone();
+    let mut y = DVector::zeros(1);
+    let mut u = DVector::zeros(1);
+
+    // 数え漏れがないこと
+    let before = n_alloc();
+    std::hint::black_box(vec![0u8; 8]);
+    assert_eq!(n_alloc(), before + 1);
+
+    let before = n_alloc();
+    for k in 0..100000 {
+        y.gemv(1.0, plant.c(), &x, 0.0);
+        pfc.update_into(&Setpoint::Sequence(&profile[k % 400..]), &y, &mut u);
+        x_next.gemv(1.0, plant.a(), &x, 0.0);
+        x_next.gemv(1.0, plant.b(), &u, 1.0);
+        std::mem::swap(&mut x, &mut x_next);
+    }
+    assert_eq!(n_alloc(), before);
+    assert!(y[0].is_finite());
+}